@@ -101,6 +101,7 @@ impl fmt::Display for MV {
 }
 
 pub mod blockdsp;
+pub mod reorder;
 
 /// The common 8x8 zigzag scan.
 pub const ZIGZAG: [usize; 64] = [
@@ -0,0 +1,100 @@
+//! Output frame reordering.
+
+/// Queue for returning decoded frames in display order.
+///
+/// Decoders push frames together with their presentation order index as they are decoded
+/// and the queue outputs them sorted by that index.
+/// Maximum reordering depth is the number of frames that may be decoded before the frame that should be displayed first
+/// (e.g. one for the usual I-P-B coding scheme), the queue holds that many frames before it starts to output them.
+///
+/// # Examples
+///
+/// ```
+/// use nihav_codec_support::codecs::reorder::FrameReorderQueue;
+///
+/// let mut queue = FrameReorderQueue::new(1);
+/// queue.push(0, "I");
+/// assert_eq!(queue.get_frame(), None); // the next decoded frame may be displayed earlier
+/// queue.push(2, "P");
+/// assert_eq!(queue.get_frame(), Some("I"));
+/// queue.push(1, "B");
+/// assert_eq!(queue.get_frame(), Some("B"));
+/// assert_eq!(queue.flush(), vec!["P"]);
+/// ```
+pub struct FrameReorderQueue<T> {
+    frames:     Vec<(u64, T)>,
+    depth:      usize,
+}
+
+impl<T> FrameReorderQueue<T> {
+    /// Constructs a new queue with the provided maximum reordering depth.
+    pub fn new(depth: usize) -> Self {
+        Self {
+            frames:     Vec::with_capacity(depth + 1),
+            depth,
+        }
+    }
+    /// Adds a decoded frame with its presentation order index to the queue.
+    pub fn push(&mut self, order: u64, frame: T) {
+        let pos = self.frames.iter().position(|(idx, _)| *idx > order).unwrap_or(self.frames.len());
+        self.frames.insert(pos, (order, frame));
+    }
+    /// Returns the next frame in display order if enough frames are buffered to be sure about it.
+    pub fn get_frame(&mut self) -> Option<T> {
+        if self.frames.len() > self.depth {
+            Some(self.frames.remove(0).1)
+        } else {
+            None
+        }
+    }
+    /// Returns the number of frames currently held in the queue.
+    pub fn len(&self) -> usize { self.frames.len() }
+    /// Reports whether the queue is empty.
+    pub fn is_empty(&self) -> bool { self.frames.is_empty() }
+    /// Returns all remaining frames in display order and empties the queue.
+    ///
+    /// This should be used at the end of stream.
+    pub fn flush(&mut self) -> Vec<T> {
+        self.frames.drain(..).map(|(_, frame)| frame).collect()
+    }
+    /// Discards all buffered frames (e.g. on seeking).
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reorder() {
+        // decode order for IBBP-coded stream
+        const ORDER: [u64; 10] = [0, 3, 1, 2, 6, 4, 5, 9, 7, 8];
+        let mut queue = FrameReorderQueue::new(2);
+        let mut out = Vec::new();
+        for &order in ORDER.iter() {
+            queue.push(order, order);
+            while let Some(frm) = queue.get_frame() {
+                out.push(frm);
+            }
+        }
+        assert_eq!(queue.len(), 2);
+        out.extend(queue.flush());
+        assert!(queue.is_empty());
+        assert_eq!(out, (0..10).collect::<Vec<u64>>());
+
+        let mut queue = FrameReorderQueue::new(0);
+        queue.push(5, 'a');
+        assert_eq!(queue.get_frame(), Some('a'));
+        assert_eq!(queue.get_frame(), None);
+
+        let mut queue = FrameReorderQueue::new(3);
+        for order in [2, 0, 1].iter() {
+            queue.push(*order, *order);
+            assert_eq!(queue.get_frame(), None);
+        }
+        queue.clear();
+        assert!(queue.flush().is_empty());
+    }
+}
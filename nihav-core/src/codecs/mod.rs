@@ -23,6 +23,8 @@ pub enum DecoderError {
     MissingReference,
     /// Feature is not implemented.
     NotImplemented,
+    /// Stream uses a profile not supported by the decoder.
+    UnsupportedProfile,
     /// Some bug in decoder. It should not happen yet it might.
     Bug,
 }
@@ -62,6 +64,18 @@ impl NADecoderSupport {
             pool_u32:       NAVideoBufferPool::new(0),
         }
     }
+    /// Constructs a new instance of `NADecoderSupport` that allocates frames from the provided pools.
+    ///
+    /// This lets several decoders (e.g. for streams with the same resolution) draw frames from common pools
+    /// instead of keeping their own ones. The pools stay shared with their original owners for as long as any of them exists.
+    /// See `NAVideoBufferPool::share()` for the thread-safety and usage requirements.
+    pub fn with_shared_pools(pool_u8: &NAVideoBufferPool<u8>, pool_u16: &NAVideoBufferPool<u16>, pool_u32: &NAVideoBufferPool<u32>) -> Self {
+        Self {
+            pool_u8:        pool_u8.share(),
+            pool_u16:       pool_u16.share(),
+            pool_u32:       pool_u32.share(),
+        }
+    }
 }
 
 impl Default for NADecoderSupport {
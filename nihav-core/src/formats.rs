@@ -403,7 +403,7 @@ impl NAPixelFormaton {
                                 if planar {
                                     start_off += 1;
                                 } else {
-                                    start_off += (comp.depth + 7) / 8;
+                                    start_off += comp.depth.div_ceil(8);
                                 }
                             }
                         }
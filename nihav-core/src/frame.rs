@@ -3,6 +3,7 @@ use std::cmp::max;
 //use std::collections::HashMap;
 use std::fmt;
 pub use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard};
 pub use crate::formats::*;
 pub use crate::refs::*;
 
@@ -91,12 +92,13 @@ pub struct NAVideoBuffer<T> {
     data:    NABufferRef<Vec<T>>,
     offs:    Vec<usize>,
     strides: Vec<usize>,
+    crop:    Option<(usize, usize, usize, usize)>,
 }
 
 impl<T: Clone> NAVideoBuffer<T> {
     /// Constructs video buffer from the provided components.
     pub fn from_raw_parts(info: NAVideoInfo, data: NABufferRef<Vec<T>>, offs: Vec<usize>, strides: Vec<usize>) -> Self {
-        Self { info, data, offs, strides }
+        Self { info, data, offs, strides, crop: None }
     }
     /// Returns the component offset (0 for all unavailable offsets).
     pub fn get_offset(&self, idx: usize) -> usize {
@@ -119,7 +121,7 @@ impl<T: Clone> NAVideoBuffer<T> {
         offs.clone_from(&self.offs);
         let mut strides: Vec<usize> = Vec::with_capacity(self.strides.len());
         strides.clone_from(&self.strides);
-        NAVideoBuffer { info: self.info, data: NABufferRef::new(data), offs, strides }
+        NAVideoBuffer { info: self.info, data: NABufferRef::new(data), offs, strides, crop: self.crop }
     }
     /// Returns stride (distance between subsequent lines) for the requested component.
     pub fn get_stride(&self, idx: usize) -> usize {
@@ -130,6 +132,83 @@ impl<T: Clone> NAVideoBuffer<T> {
     pub fn get_dimensions(&self, idx: usize) -> (usize, usize) {
         get_plane_size(&self.info, idx)
     }
+    /// Returns the visible picture area as `(x, y, width, height)` tuple (if it differs from the full picture).
+    ///
+    /// The rectangle is given in luma pixels, component data access is not affected by it.
+    pub fn get_crop_rect(&self) -> Option<(usize, usize, usize, usize)> { self.crop }
+    /// Sets the visible picture area.
+    ///
+    /// The rectangle is clipped to the picture dimensions.
+    pub fn set_crop_rect(&mut self, crop: Option<(usize, usize, usize, usize)>) {
+        self.crop = crop.map(|(x, y, w, h)| {
+                let x = x.min(self.info.width);
+                let y = y.min(self.info.height);
+                (x, y, w.min(self.info.width - x), h.min(self.info.height - y))
+            });
+    }
+    /// Returns an iterator over the stored picture planes.
+    ///
+    /// Every element contains the component format definition, plane data and its stride.
+    /// Packed formats are stored as a single plane that is reported with the first component definition.
+    pub fn planes(&self) -> impl Iterator<Item = (NAPixelChromaton, &[T], usize)> {
+        let data = self.data.as_slice();
+        self.get_plane_ranges().into_iter().enumerate().map(move |(i, (start, end))| {
+                (self.info.format.get_chromaton(i).unwrap(), &data[start..end], self.strides[i])
+            })
+    }
+    /// Returns an iterator over the stored picture planes with mutable access to the plane data.
+    ///
+    /// See [`planes`] for the details.
+    ///
+    /// [`planes`]: #method.planes
+    pub fn planes_mut(&mut self) -> impl Iterator<Item = (NAPixelChromaton, &mut [T], usize)> {
+        let ranges = self.get_plane_ranges();
+        let mut order: Vec<usize> = (0..ranges.len()).collect();
+        order.sort_by_key(|&i| ranges[i].0);
+
+        let mut planes: Vec<Option<&mut [T]>> = Vec::with_capacity(ranges.len());
+        planes.resize_with(ranges.len(), || None);
+        let mut rest = self.data.as_mut_slice();
+        let mut pos = 0;
+        for idx in order {
+            let (start, end) = ranges[idx];
+            let (_, tail) = rest.split_at_mut(start - pos);
+            let (plane, tail) = tail.split_at_mut(end - start);
+            planes[idx] = Some(plane);
+            rest = tail;
+            pos = end;
+        }
+        let info = self.info;
+        let strides = self.strides.clone();
+        planes.into_iter().enumerate().map(move |(i, plane)| {
+                (info.format.get_chromaton(i).unwrap(), plane.unwrap(), strides[i])
+            })
+    }
+    /// Returns the index of the plane where the requested component (e.g. Y, U, V or alpha) is stored.
+    pub fn plane_by_component(&self, comp: usize) -> Option<usize> {
+        let chr = self.info.format.get_chromaton(comp)?;
+        if self.info.format.is_paletted() || chr.is_packed() {
+            Some(0)
+        } else {
+            let plane = chr.get_offset() as usize;
+            if plane < self.strides.len() { Some(plane) } else { None }
+        }
+    }
+    fn get_plane_ranges(&self) -> Vec<(usize, usize)> {
+        let nplanes = self.strides.len().min(self.info.format.get_num_comp());
+        let mut ranges = Vec::with_capacity(nplanes);
+        for i in 0..nplanes {
+            let start = self.get_offset(i);
+            let mut end = self.data.len();
+            for &off in self.offs.iter() {
+                if off > start && off < end {
+                    end = off;
+                }
+            }
+            ranges.push((start, end));
+        }
+        ranges
+    }
     /// Converts current instance into buffer reference.
     pub fn into_ref(self) -> NABufferRef<Self> {
         NABufferRef::new(self)
@@ -151,6 +230,117 @@ impl<T: Clone> NAVideoBuffer<T> {
     }
 }
 
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME:        u64 = 0x0000_0100_0000_01B3;
+
+impl<T: Copy + Into<u64>> NAVideoBuffer<T> {
+    /// Calculates a hash of the visible picture data for the requested plane.
+    ///
+    /// The hash is 64-bit FNV-1a calculated over the plane elements in raster order,
+    /// each element being fed as `size_of::<T>()` bytes in little-endian order.
+    /// Only the picture area (or the crop rectangle if it is set, scaled by the plane subsampling)
+    /// is hashed so line padding and alignment area do not affect the result.
+    /// For paletted formats the second plane is the palette and it is hashed whole.
+    /// For non-existing planes the hash of empty data is returned.
+    pub fn plane_hash(&self, plane: usize) -> u64 {
+        self.hash_plane(plane, FNV_OFFSET_BASIS)
+    }
+    /// Calculates a hash of the visible picture data for all planes.
+    ///
+    /// This is equivalent to hashing the visible data of all planes in their order with a single 64-bit FNV-1a state (see [`plane_hash`]).
+    ///
+    /// [`plane_hash`]: #method.plane_hash
+    pub fn frame_hash(&self) -> u64 {
+        (0..self.strides.len()).fold(FNV_OFFSET_BASIS, |hash, plane| self.hash_plane(plane, hash))
+    }
+    fn hash_plane(&self, plane: usize, mut hash: u64) -> u64 {
+        if plane >= self.strides.len() {
+            return hash;
+        }
+        let elsize = std::mem::size_of::<T>();
+        let data = self.data.as_slice();
+        let start = self.get_offset(plane);
+        let fmt = self.info.format;
+        if fmt.is_paletted() && plane > 0 {
+            for &el in data[start..].iter() {
+                hash = fnv1a_elem(hash, el.into(), elsize);
+            }
+            return hash;
+        }
+        let (hs, vs, elems) = match fmt.get_chromaton(plane) {
+                Some(chr) if !fmt.is_paletted() && !chr.is_packed() => {
+                    let (hs, vs) = chr.get_subsampling();
+                    (hs, vs, 1)
+                },
+                _ if fmt.is_paletted() => (0, 0, 1),
+                _ => (0, 0, (usize::from(fmt.get_elem_size()) / elsize).max(1)),
+            };
+        let (x, y, w, h) = self.crop.unwrap_or((0, 0, self.info.width, self.info.height));
+        let x0 = x >> hs;
+        let x1 = (x + w + (1 << hs) - 1) >> hs;
+        let y0 = y >> vs;
+        let y1 = (y + h + (1 << vs) - 1) >> vs;
+        let stride = self.strides[plane];
+        for line in data[start..].chunks(stride).skip(y0).take(y1 - y0) {
+            for &el in line[x0 * elems..x1 * elems].iter() {
+                hash = fnv1a_elem(hash, el.into(), elsize);
+            }
+        }
+        hash
+    }
+}
+
+fn fnv1a_elem(mut hash: u64, val: u64, elsize: usize) -> u64 {
+    for b in val.to_le_bytes().iter().take(elsize) {
+        hash = (hash ^ u64::from(*b)).wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+fn change_depth(fmt: NAPixelFormaton, new_depth: impl Fn(u8) -> u8) -> Result<NAPixelFormaton, AllocatorError> {
+    if fmt.is_paletted() {
+        return Err(AllocatorError::FormatError);
+    }
+    let mut new_fmt = fmt;
+    for chr in new_fmt.comp_info.iter_mut().take(fmt.get_num_comp()) {
+        match chr {
+            Some(ref mut chr) if !chr.is_packed() => {
+                chr.depth = new_depth(chr.depth);
+            },
+            _ => return Err(AllocatorError::FormatError),
+        };
+    }
+    Ok(new_fmt)
+}
+
+impl NAVideoBuffer<u16> {
+    /// Converts planar high bit depth buffer into 8-bit one.
+    ///
+    /// All component values are shifted right by the provided amount of bits and saturated to 8 bits (so e.g. 10-bit content converted with `shift` = 0 will have all values above 255 clipped).
+    /// Output buffer has the same layout and its format has component depths reduced accordingly.
+    /// Packed and paletted formats are not supported.
+    pub fn to_u8(&self, shift: u8) -> Result<NAVideoBuffer<u8>, AllocatorError> {
+        let shift = shift.min(15);
+        let format = change_depth(self.info.format, |depth| depth.saturating_sub(shift).clamp(1, 8))?;
+        let data: Vec<u8> = self.data.iter().map(|&el| (el >> shift).min(255) as u8).collect();
+        Ok(NAVideoBuffer { info: NAVideoInfo { format, ..self.info }, data: NABufferRef::new(data), offs: self.offs.clone(), strides: self.strides.clone(), crop: self.crop })
+    }
+}
+
+impl NAVideoBuffer<u8> {
+    /// Converts planar 8-bit buffer into high bit depth one.
+    ///
+    /// All component values are shifted left by the provided amount of bits (up to 8) so no truncation occurs.
+    /// Output buffer has the same layout and its format has component depths increased accordingly.
+    /// Packed and paletted formats are not supported.
+    pub fn to_u16(&self, shift: u8) -> Result<NAVideoBuffer<u16>, AllocatorError> {
+        let shift = shift.min(8);
+        let format = change_depth(self.info.format, |depth| depth + shift)?;
+        let data: Vec<u16> = self.data.iter().map(|&el| u16::from(el) << shift).collect();
+        Ok(NAVideoBuffer { info: NAVideoInfo { format, ..self.info }, data: NABufferRef::new(data), offs: self.offs.clone(), strides: self.strides.clone(), crop: self.crop })
+    }
+}
+
 /// A specialised type for reference-counted `NAVideoBuffer`.
 pub type NAVideoBufferRef<T> = NABufferRef<NAVideoBuffer<T>>;
 
@@ -332,7 +522,7 @@ pub fn alloc_video_buffer(vinfo: NAVideoInfo, align: u8) -> Result<NABufferType,
         offs.push(stride * height);
         strides.push(stride);
         let data: Vec<u8> = vec![0; new_size.unwrap()];
-        let buf: NAVideoBuffer<u8> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides };
+        let buf: NAVideoBuffer<u8> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides, crop: None };
         Ok(NABufferType::Video(buf.into_ref()))
     } else if !all_packed {
         for i in 0..fmt.get_num_comp() {
@@ -351,15 +541,15 @@ pub fn alloc_video_buffer(vinfo: NAVideoInfo, align: u8) -> Result<NABufferType,
         }
         if max_depth <= 8 {
             let data: Vec<u8> = vec![0; new_size];
-            let buf: NAVideoBuffer<u8> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides };
+            let buf: NAVideoBuffer<u8> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides, crop: None };
             Ok(NABufferType::Video(buf.into_ref()))
         } else if max_depth <= 16 {
             let data: Vec<u16> = vec![0; new_size];
-            let buf: NAVideoBuffer<u16> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides };
+            let buf: NAVideoBuffer<u16> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides, crop: None };
             Ok(NABufferType::Video16(buf.into_ref()))
         } else {
             let data: Vec<u32> = vec![0; new_size];
-            let buf: NAVideoBuffer<u32> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides };
+            let buf: NAVideoBuffer<u32> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides, crop: None };
             Ok(NABufferType::Video32(buf.into_ref()))
         }
     } else if all_bytealigned || unfit_elem_size {
@@ -371,7 +561,7 @@ pub fn alloc_video_buffer(vinfo: NAVideoInfo, align: u8) -> Result<NABufferType,
         new_size = new_sz.unwrap();
         let data: Vec<u8> = vec![0; new_size];
         strides.push(line_sz.unwrap());
-        let buf: NAVideoBuffer<u8> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides };
+        let buf: NAVideoBuffer<u8> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides, crop: None };
         Ok(NABufferType::VideoPacked(buf.into_ref()))
     } else {
         let elem_sz = fmt.get_elem_size();
@@ -382,13 +572,13 @@ pub fn alloc_video_buffer(vinfo: NAVideoInfo, align: u8) -> Result<NABufferType,
             2 => {
                     let data: Vec<u16> = vec![0; new_size];
                     strides.push(width);
-                    let buf: NAVideoBuffer<u16> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides };
+                    let buf: NAVideoBuffer<u16> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides, crop: None };
                     Ok(NABufferType::Video16(buf.into_ref()))
                 },
             4 => {
                     let data: Vec<u32> = vec![0; new_size];
                     strides.push(width);
-                    let buf: NAVideoBuffer<u32> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides };
+                    let buf: NAVideoBuffer<u32> = NAVideoBuffer { data: NABufferRef::new(data), info: vinfo, offs, strides, crop: None };
                     Ok(NABufferType::Video32(buf.into_ref()))
                 },
             _ => unreachable!(),
@@ -408,33 +598,135 @@ pub fn copy_buffer(buf: &NABufferType) -> NABufferType {
     buf.clone()
 }
 
+struct PoolState<T:Copy> {
+    pool:       Vec<NAVideoBufferRef<T>>,
+    max_len:    usize,
+    // the sum of the frames reserved through all pool handles
+    add_len:    usize,
+    vinfo:      Option<NAVideoInfo>,
+}
+
 /// Video frame pool.
 ///
 /// This structure allows codec to effectively reuse old frames instead of allocating and de-allocating frames every time.
 /// Caller can also reserve some frames for its own purposes e.g. display queue.
+///
+/// A pool may be shared between several decoders (e.g. decoding streams with the same resolution) with [`share`].
+/// All handles operate on the same set of frames and the number of reserved frames is the sum of the
+/// numbers set by every handle with [`set_dec_bufs`]. A frame is handed out only when nobody else references it,
+/// so decoders sharing the pool never get the same frame. The pool state is protected by a mutex and frame
+/// reference counts are atomic, so the handles may be used from different threads. All users are expected
+/// to work with the same frame format though: preallocating frames of a different format or calling [`reset`]
+/// through one handle releases the free frames of the whole pool (the frames currently in use stay intact).
+///
+/// [`share`]: #method.share
+/// [`set_dec_bufs`]: #method.set_dec_bufs
+/// [`reset`]: #method.reset
 pub struct NAVideoBufferPool<T:Copy> {
-    pool:       Vec<NAVideoBufferRef<T>>,
-    max_len:    usize,
+    state:      Arc<Mutex<PoolState<T>>>,
     add_len:    usize,
+    #[cfg(feature = "alloc-failures")]
+    fail_alloc: bool,
+}
+
+fn same_layout(a: &NAVideoInfo, b: &NAVideoInfo) -> bool {
+    a.width == b.width && a.height == b.height && a.format == b.format
+}
+
+impl<T:Copy> PoolState<T> {
+    fn is_compatible(&self, vinfo: &NAVideoInfo) -> bool {
+        if let Some(ref cur) = self.vinfo {
+            same_layout(cur, vinfo)
+        } else {
+            false
+        }
+    }
+    fn reset(&mut self) {
+        self.pool.clear();
+        self.vinfo = None;
+        self.pool.shrink_to(self.max_len);
+    }
+    fn get_num_used(&self) -> usize {
+        self.pool.iter().filter(|el| el.get_num_refs() != 1).count()
+    }
 }
 
 impl<T:Copy> NAVideoBufferPool<T> {
     /// Constructs a new `NAVideoBufferPool` instance.
     pub fn new(max_len: usize) -> Self {
+        let state = PoolState {
+                pool:       Vec::with_capacity(max_len),
+                max_len,
+                add_len:    0,
+                vinfo:      None,
+            };
         Self {
-            pool:       Vec::with_capacity(max_len),
-            max_len,
-            add_len: 0,
+            state:      Arc::new(Mutex::new(state)),
+            add_len:    0,
+            #[cfg(feature = "alloc-failures")]
+            fail_alloc: false,
         }
     }
+    /// Creates another handle to the same pool.
+    ///
+    /// The new handle does not reserve any frames initially.
+    pub fn share(&self) -> Self {
+        Self {
+            state:      Arc::clone(&self.state),
+            add_len:    0,
+            #[cfg(feature = "alloc-failures")]
+            fail_alloc: false,
+        }
+    }
+    /// Reports whether both handles refer to the same pool.
+    pub fn is_shared_with(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+    fn lock(&self) -> MutexGuard<'_, PoolState<T>> {
+        // the state stays consistent even if another user panicked
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+    /// Makes the next buffer request fail.
+    ///
+    /// The next call to [`get_free`], [`get_copy`] or `prealloc_video` fails as if the memory could not be allocated
+    /// (the latter returns `AllocatorError::TooLargeDimensions`), the calls after it work normally.
+    /// This is intended only for testing error handling and is available with `alloc-failures` feature.
+    ///
+    /// [`get_free`]: #method.get_free
+    /// [`get_copy`]: #method.get_copy
+    #[cfg(feature = "alloc-failures")]
+    pub fn fail_next_alloc(&mut self) {
+        self.fail_alloc = true;
+    }
+    fn check_alloc_failure(&mut self) -> Result<(), AllocatorError> {
+        #[cfg(feature = "alloc-failures")]
+        if std::mem::take(&mut self.fail_alloc) {
+            return Err(AllocatorError::TooLargeDimensions);
+        }
+        Ok(())
+    }
     /// Sets the number of buffers reserved for the user.
     pub fn set_dec_bufs(&mut self, add_len: usize) {
+        let mut state = self.lock();
+        state.add_len = state.add_len - self.add_len + add_len;
+        drop(state);
         self.add_len = add_len;
     }
+    /// Reports whether the pool holds frames with the same dimensions and plane layout as requested.
+    ///
+    /// An empty pool is not compatible with any format.
+    pub fn is_compatible(&self, vinfo: &NAVideoInfo) -> bool {
+        self.lock().is_compatible(vinfo)
+    }
     /// Returns an unused buffer from the pool.
+    ///
+    /// Only frames matching the format the pool was configured for are returned.
     pub fn get_free(&mut self) -> Option<NAVideoBufferRef<T>> {
-        for e in self.pool.iter() {
-            if e.get_num_refs() == 1 {
+        self.check_alloc_failure().ok()?;
+        let state = self.lock();
+        let vinfo = state.vinfo?;
+        for e in state.pool.iter() {
+            if e.get_num_refs() == 1 && same_layout(&e.get_info(), &vinfo) {
                 return Some(e.clone());
             }
         }
@@ -442,85 +734,117 @@ impl<T:Copy> NAVideoBufferPool<T> {
     }
     /// Clones provided frame data into a free pool frame.
     pub fn get_copy(&mut self, rbuf: &NAVideoBufferRef<T>) -> Option<NAVideoBufferRef<T>> {
+        if !self.is_compatible(&rbuf.get_info()) {
+            return None;
+        }
         let mut dbuf = self.get_free()?;
         dbuf.data.copy_from_slice(&rbuf.data);
+        dbuf.crop = rbuf.crop;
         Some(dbuf)
     }
-    /// Clears the pool from all frames.
+    /// Clears the pool from all frames and releases the memory used for keeping them.
+    ///
+    /// Frames that are still in use are not affected and will be released once the last reference to them is dropped.
     pub fn reset(&mut self) {
-        self.pool.clear();
+        self.lock().reset();
+    }
+    /// Releases free frames beyond the requested number.
+    ///
+    /// Only unused frames are removed from the pool, frames that are currently in use are kept intact.
+    pub fn shrink_to(&mut self, count: usize) {
+        let mut nfree = 0;
+        self.lock().pool.retain(|el| {
+                if el.get_num_refs() != 1 {
+                    return true;
+                }
+                nfree += 1;
+                nfree <= count
+            });
     }
     /// Returns the number of frames currently in use.
     pub fn get_num_used(&self) -> usize {
-        self.pool.iter().filter(|el| el.get_num_refs() != 1).count()
+        self.lock().get_num_used()
+    }
+    /// Returns the total and the free number of frames in the pool.
+    pub fn buffer_count(&self) -> (usize, usize) {
+        let state = self.lock();
+        (state.pool.len(), state.pool.len() - state.get_num_used())
+    }
+    /// Returns the amount of memory in bytes taken by the frame data of all frames in the pool.
+    ///
+    /// Both free and used frames are counted. The value reflects the frames allocated at the moment, not the peak usage.
+    pub fn memory_used(&self) -> usize {
+        self.lock().pool.iter().fold(0, |acc, el| acc + el.data.len() * std::mem::size_of::<T>())
     }
     /// Adds a manually allocated frame to the pool.
+    ///
+    /// If the pool has not been configured yet, the frame format becomes the pool format.
     pub fn add_frame(&mut self, buf: NAVideoBufferRef<T>) {
-        self.pool.push(buf);
+        let mut state = self.lock();
+        if state.vinfo.is_none() {
+            state.vinfo = Some(buf.get_info());
+        }
+        state.pool.push(buf);
     }
     /// Returns current video format (if available).
     pub fn get_info(&self) -> Option<NAVideoInfo> {
-        if !self.pool.is_empty() {
-            Some(self.pool[0].get_info())
-        } else {
-            None
+        self.lock().pool.first().map(|buf| buf.get_info())
+    }
+    fn prealloc_frames(&mut self, vinfo: NAVideoInfo, align: u8, get_buf: fn(NABufferType) -> Option<NAVideoBufferRef<T>>) -> Result<(), AllocatorError> {
+        self.check_alloc_failure()?;
+        let mut state = self.lock();
+        if !state.is_compatible(&vinfo) {
+            state.reset();
         }
+        state.vinfo = Some(vinfo);
+        let nbufs = (state.max_len + state.add_len).saturating_sub(state.pool.len());
+        for _ in 0..nbufs {
+            let buf = get_buf(alloc_video_buffer(vinfo, align)?).ok_or(AllocatorError::FormatError)?;
+            state.pool.push(buf);
+        }
+        Ok(())
+    }
+}
+
+impl<T:Copy> Drop for NAVideoBufferPool<T> {
+    fn drop(&mut self) {
+        self.set_dec_bufs(0);
     }
 }
 
 impl NAVideoBufferPool<u8> {
     /// Allocates the target amount of video frames using [`alloc_video_buffer`].
     ///
+    /// If the pool contains frames with a different size or layout, they are released and new frames are allocated instead.
+    ///
     /// [`alloc_video_buffer`]: ./fn.alloc_video_buffer.html
     pub fn prealloc_video(&mut self, vinfo: NAVideoInfo, align: u8) -> Result<(), AllocatorError> {
-        let nbufs = self.max_len + self.add_len - self.pool.len();
-        for _ in 0..nbufs {
-            let vbuf = alloc_video_buffer(vinfo, align)?;
-            if let NABufferType::Video(buf) = vbuf {
-                self.pool.push(buf);
-            } else if let NABufferType::VideoPacked(buf) = vbuf {
-                self.pool.push(buf);
-            } else {
-                return Err(AllocatorError::FormatError);
-            }
-        }
-        Ok(())
+        self.prealloc_frames(vinfo, align, |vbuf| match vbuf {
+                NABufferType::Video(buf) | NABufferType::VideoPacked(buf) => Some(buf),
+                _ => None,
+            })
     }
 }
 
 impl NAVideoBufferPool<u16> {
     /// Allocates the target amount of video frames using [`alloc_video_buffer`].
     ///
+    /// If the pool contains frames with a different size or layout, they are released and new frames are allocated instead.
+    ///
     /// [`alloc_video_buffer`]: ./fn.alloc_video_buffer.html
     pub fn prealloc_video(&mut self, vinfo: NAVideoInfo, align: u8) -> Result<(), AllocatorError> {
-        let nbufs = self.max_len + self.add_len - self.pool.len();
-        for _ in 0..nbufs {
-            let vbuf = alloc_video_buffer(vinfo, align)?;
-            if let NABufferType::Video16(buf) = vbuf {
-                self.pool.push(buf);
-            } else {
-                return Err(AllocatorError::FormatError);
-            }
-        }
-        Ok(())
+        self.prealloc_frames(vinfo, align, |vbuf| if let NABufferType::Video16(buf) = vbuf { Some(buf) } else { None })
     }
 }
 
 impl NAVideoBufferPool<u32> {
     /// Allocates the target amount of video frames using [`alloc_video_buffer`].
     ///
+    /// If the pool contains frames with a different size or layout, they are released and new frames are allocated instead.
+    ///
     /// [`alloc_video_buffer`]: ./fn.alloc_video_buffer.html
     pub fn prealloc_video(&mut self, vinfo: NAVideoInfo, align: u8) -> Result<(), AllocatorError> {
-        let nbufs = self.max_len + self.add_len - self.pool.len();
-        for _ in 0..nbufs {
-            let vbuf = alloc_video_buffer(vinfo, align)?;
-            if let NABufferType::Video32(buf) = vbuf {
-                self.pool.push(buf);
-            } else {
-                return Err(AllocatorError::FormatError);
-            }
-        }
-        Ok(())
+        self.prealloc_frames(vinfo, align, |vbuf| if let NABufferType::Video32(buf) = vbuf { Some(buf) } else { None })
     }
 }
 
@@ -629,3 +953,275 @@ fn get_plane_size(info: &NAVideoInfo, idx: usize) -> (usize, usize) {
     let h = (info.get_height() + ((1 << vs) - 1)) >> vs;
     (w, h)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pool_shrink() {
+        let vinfo = NAVideoInfo::new(16, 16, false, YUV420_FORMAT);
+        let mut pool: NAVideoBufferPool<u8> = NAVideoBufferPool::new(0);
+        pool.set_dec_bufs(4);
+        pool.prealloc_video(vinfo, 4).unwrap();
+        assert_eq!(pool.buffer_count().0, 4);
+
+        let mut bufs = Vec::new();
+        for _ in 0..4 {
+            bufs.push(pool.get_free().unwrap());
+        }
+        assert!(pool.get_free().is_none());
+        assert_eq!(pool.get_num_used(), 4);
+        bufs.clear();
+        assert_eq!(pool.get_num_used(), 0);
+
+        pool.shrink_to(1);
+        assert_eq!(pool.buffer_count().0, 1);
+        assert_eq!(pool.get_num_used(), 0);
+
+        pool.set_dec_bufs(3);
+        pool.prealloc_video(vinfo, 4).unwrap();
+        let used = pool.get_free().unwrap();
+        pool.shrink_to(1);
+        assert_eq!(pool.buffer_count().0, 2);
+        assert_eq!(pool.get_num_used(), 1);
+        pool.shrink_to(0);
+        assert_eq!(pool.buffer_count().0, 1);
+        assert_eq!(pool.get_num_used(), 1);
+        drop(used);
+
+        pool.reset();
+        assert_eq!(pool.buffer_count().0, 0);
+        assert!(pool.get_free().is_none());
+    }
+
+    #[test]
+    fn test_pool_overfill() {
+        let vinfo = NAVideoInfo::new(16, 16, false, YUV420_FORMAT);
+        let mut pool: NAVideoBufferPool<u8> = NAVideoBufferPool::new(0);
+        pool.set_dec_bufs(3);
+        pool.prealloc_video(vinfo, 4).unwrap();
+        let buf = pool.get_free().unwrap();
+        pool.add_frame(NABufferRef::new(buf.copy_buffer()));
+        drop(buf);
+        // the pool already holds more frames than requested so nothing new should be allocated
+        pool.prealloc_video(vinfo, 4).unwrap();
+        assert_eq!(pool.buffer_count(), (4, 4));
+
+        let vinfo16 = NAVideoInfo::new(16, 16, false, change_depth(YUV420_FORMAT, |_| 10).unwrap());
+        let mut pool16: NAVideoBufferPool<u16> = NAVideoBufferPool::new(1);
+        pool16.prealloc_video(vinfo16, 4).unwrap();
+        let buf = pool16.get_free().unwrap();
+        pool16.add_frame(NABufferRef::new(buf.copy_buffer()));
+        drop(buf);
+        pool16.prealloc_video(vinfo16, 4).unwrap();
+        assert_eq!(pool16.buffer_count(), (2, 2));
+
+        let vinfo32 = NAVideoInfo::new(16, 16, false, change_depth(YUV420_FORMAT, |_| 20).unwrap());
+        let mut pool32: NAVideoBufferPool<u32> = NAVideoBufferPool::new(1);
+        pool32.prealloc_video(vinfo32, 4).unwrap();
+        let buf = pool32.get_free().unwrap();
+        pool32.add_frame(NABufferRef::new(buf.copy_buffer()));
+        drop(buf);
+        pool32.prealloc_video(vinfo32, 4).unwrap();
+        assert_eq!(pool32.buffer_count(), (2, 2));
+    }
+
+    #[test]
+    fn test_pool_share() {
+        let vinfo = NAVideoInfo::new(16, 16, false, YUV420_FORMAT);
+        let mut pool: NAVideoBufferPool<u8> = NAVideoBufferPool::new(0);
+        let mut pool2 = pool.share();
+        assert!(pool.is_shared_with(&pool2));
+        assert!(!pool.is_shared_with(&NAVideoBufferPool::new(0)));
+        // reservations from all handles add up
+        pool.set_dec_bufs(2);
+        pool2.set_dec_bufs(3);
+        pool.prealloc_video(vinfo, 4).unwrap();
+        assert_eq!(pool2.buffer_count(), (5, 5));
+        pool2.set_dec_bufs(1);
+        pool2.prealloc_video(vinfo, 4).unwrap();
+        assert_eq!(pool.buffer_count(), (5, 5));
+
+        let mut bufs = Vec::new();
+        for i in 0..5 {
+            let mut buf = if (i & 1) == 0 { pool.get_free() } else { pool2.get_free() }.unwrap();
+            for el in buf.get_data_mut().unwrap().iter_mut() {
+                *el = i;
+            }
+            bufs.push(buf);
+        }
+        assert!(pool.get_free().is_none());
+        assert!(pool2.get_free().is_none());
+        assert_eq!(pool2.get_num_used(), 5);
+        for (i, buf) in bufs.iter().enumerate() {
+            assert!(buf.get_data().iter().all(|&el| usize::from(el) == i));
+        }
+        bufs.truncate(2);
+        assert_eq!(pool.buffer_count(), (5, 3));
+
+        // frames reserved by a released handle are not allocated any more
+        drop(pool2);
+        pool.shrink_to(0);
+        pool.prealloc_video(vinfo, 4).unwrap();
+        assert_eq!(pool.buffer_count(), (2, 0));
+        bufs.clear();
+        assert_eq!(pool.buffer_count(), (2, 2));
+    }
+
+    #[test]
+    fn test_pool_memory() {
+        let vinfo = NAVideoInfo::new(16, 16, false, YUV420_FORMAT);
+        let mut pool: NAVideoBufferPool<u8> = NAVideoBufferPool::new(3);
+        assert_eq!(pool.memory_used(), 0);
+        assert_eq!(pool.buffer_count(), (0, 0));
+        pool.prealloc_video(vinfo, 4).unwrap();
+        assert_eq!(pool.memory_used(), 3 * (16 * 16 + 2 * 8 * 8));
+        let used = pool.get_free().unwrap();
+        assert_eq!(pool.buffer_count(), (3, 2));
+        pool.shrink_to(0);
+        assert_eq!(pool.buffer_count(), (1, 0));
+        assert_eq!(pool.memory_used(), 16 * 16 + 2 * 8 * 8);
+        drop(used);
+        assert_eq!(pool.buffer_count(), (1, 1));
+
+        let vinfo16 = NAVideoInfo::new(16, 16, false, change_depth(YUV420_FORMAT, |_| 10).unwrap());
+        let mut pool16: NAVideoBufferPool<u16> = NAVideoBufferPool::new(2);
+        pool16.prealloc_video(vinfo16, 4).unwrap();
+        assert_eq!(pool16.memory_used(), 2 * 2 * (16 * 16 + 2 * 8 * 8));
+    }
+
+    #[test]
+    fn test_pool_layout() {
+        let planar = NAVideoInfo::new(16, 16, false, YUV420_FORMAT);
+        let packed = NAVideoInfo::new(16, 16, false, RGB24_FORMAT);
+        let mut pool: NAVideoBufferPool<u8> = NAVideoBufferPool::new(2);
+        assert!(!pool.is_compatible(&planar));
+        pool.prealloc_video(planar, 4).unwrap();
+        assert!(pool.is_compatible(&planar));
+        assert!(pool.is_compatible(&NAVideoInfo::new(16, 16, true, YUV420_FORMAT)));
+        assert!(!pool.is_compatible(&packed));
+        assert!(!pool.is_compatible(&NAVideoInfo::new(32, 16, false, YUV420_FORMAT)));
+        let held = pool.get_free().unwrap();
+
+        pool.prealloc_video(packed, 4).unwrap();
+        assert!(pool.is_compatible(&packed));
+        assert_eq!(pool.buffer_count(), (2, 2));
+        let buf = pool.get_free().unwrap();
+        assert!(buf.get_info() == packed);
+        assert_eq!(buf.get_stride(0), 16 * 3);
+        assert!(pool.get_copy(&held).is_none());
+
+        pool.add_frame(held);
+        assert_eq!(pool.buffer_count(), (3, 2));
+        drop(buf);
+        let mut bufs = Vec::new();
+        while let Some(buf) = pool.get_free() {
+            assert!(buf.get_info() == packed);
+            bufs.push(buf);
+        }
+        assert_eq!(bufs.len(), 2);
+    }
+
+    #[test]
+    fn test_depth_conversion() {
+        let vinfo = NAVideoInfo::new(32, 16, false, YUV420_FORMAT);
+        let mut buf = alloc_video_buffer(vinfo, 4).unwrap().get_vbuf().unwrap();
+        for (i, el) in buf.get_data_mut().unwrap().iter_mut().enumerate() {
+            *el = i as u8;
+        }
+        buf.set_crop_rect(Some((0, 0, 30, 16)));
+
+        let buf16 = buf.to_u16(2).unwrap();
+        assert_eq!(buf16.get_info().get_format().get_chromaton(0).unwrap().get_depth(), 10);
+        assert_eq!(buf16.get_info().get_format().get_chromaton(2).unwrap().get_subsampling(), (1, 1));
+        assert_eq!(buf16.get_dimensions(1), buf.get_dimensions(1));
+        assert_eq!(buf16.get_stride(2), buf.get_stride(2));
+        assert_eq!(buf16.get_offset(2), buf.get_offset(2));
+        assert_eq!(buf16.get_crop_rect(), Some((0, 0, 30, 16)));
+        assert!(buf16.get_data().iter().zip(buf.get_data().iter()).all(|(&a, &b)| a == u16::from(b) * 4));
+
+        let buf8 = buf16.to_u8(2).unwrap();
+        assert!(buf8.get_info() == buf.get_info());
+        assert_eq!(buf8.get_data(), buf.get_data());
+
+        let clipped = buf16.to_u8(1).unwrap();
+        assert_eq!(clipped.get_info().get_format().get_chromaton(0).unwrap().get_depth(), 8);
+        assert!(clipped.get_data().iter().zip(buf.get_data().iter()).all(|(&a, &b)| a == (u16::from(b) * 2).min(255) as u8));
+
+        let vinfo = NAVideoInfo::new(16, 16, false, RGB24_FORMAT);
+        let packed = alloc_video_buffer(vinfo, 4).unwrap().get_vbuf().unwrap();
+        assert!(packed.to_u16(2).is_err());
+    }
+
+    #[test]
+    fn test_planes() {
+        let vinfo = NAVideoInfo::new(20, 16, false, YUV420_FORMAT);
+        let mut buf = alloc_video_buffer(vinfo, 4).unwrap().get_vbuf().unwrap();
+        let planes: Vec<(NAPixelChromaton, usize, usize)> = buf.planes().map(|(chr, data, stride)| (chr, data.len(), stride)).collect();
+        assert_eq!(planes.len(), 3);
+        assert_eq!((planes[0].1, planes[0].2), (32 * 16, 32));
+        assert_eq!((planes[1].1, planes[1].2), (16 * 8, 16));
+        assert_eq!((planes[2].1, planes[2].2), (16 * 8, 16));
+        assert_eq!(planes[0].0.get_subsampling(), (0, 0));
+        assert_eq!(planes[1].0.get_subsampling(), (1, 1));
+
+        for (i, (_, data, _)) in buf.planes_mut().enumerate() {
+            for el in data.iter_mut() {
+                *el = i as u8;
+            }
+        }
+        for comp in 0..3 {
+            let plane = buf.plane_by_component(comp).unwrap();
+            assert_eq!(plane, comp);
+            assert_eq!(buf.get_data()[buf.get_offset(plane)], comp as u8);
+        }
+        assert_eq!(buf.plane_by_component(3), None);
+
+        let vinfo = NAVideoInfo::new(16, 16, false, YUVA410_FORMAT);
+        let buf = alloc_video_buffer(vinfo, 4).unwrap().get_vbuf().unwrap();
+        assert_eq!(buf.planes().count(), 4);
+        assert_eq!(buf.plane_by_component(3), Some(3));
+
+        let vinfo = NAVideoInfo::new(16, 16, false, RGB24_FORMAT);
+        let buf = alloc_video_buffer(vinfo, 4).unwrap().get_vbuf().unwrap();
+        let planes: Vec<(usize, usize)> = buf.planes().map(|(_, data, stride)| (data.len(), stride)).collect();
+        assert_eq!(planes, vec![(16 * 16 * 3, 16 * 3)]);
+        assert_eq!(buf.plane_by_component(2), Some(0));
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        let vinfo = NAVideoInfo::new(6, 6, false, YUV420_FORMAT);
+        let mut bufs = Vec::new();
+        for &align in [0, 4].iter() {
+            let mut buf = alloc_video_buffer(vinfo, align).unwrap().get_vbuf().unwrap();
+            for (plane, (_, data, stride)) in buf.planes_mut().enumerate() {
+                let (w, h) = if plane == 0 { (6, 6) } else { (3, 3) };
+                for (y, line) in data.chunks_mut(stride).enumerate() {
+                    for (x, el) in line.iter_mut().enumerate() {
+                        *el = if x < w && y < h { (plane * 64 + y * 8 + x) as u8 } else { 0xFF };
+                    }
+                }
+            }
+            bufs.push(buf);
+        }
+        assert_eq!(bufs[0].plane_hash(0), 0x5C38_0923_02CD_E3F9);
+        assert_eq!(bufs[0].frame_hash(), 0xD35F_8C74_DF82_049B);
+        for plane in 0..3 {
+            assert_eq!(bufs[0].plane_hash(plane), bufs[1].plane_hash(plane));
+        }
+        assert_eq!(bufs[0].frame_hash(), bufs[1].frame_hash());
+        assert_eq!(bufs[0].plane_hash(3), FNV_OFFSET_BASIS);
+
+        let mut buf = bufs.pop().unwrap();
+        buf.set_crop_rect(Some((2, 2, 4, 4)));
+        let hash = buf.frame_hash();
+        assert_ne!(hash, bufs[0].frame_hash());
+        buf.get_data_mut().unwrap()[0] = 0;
+        assert_eq!(buf.frame_hash(), hash);
+        let off = buf.get_offset(1) + buf.get_stride(1) + 1;
+        buf.get_data_mut().unwrap()[off] = 0;
+        assert_ne!(buf.frame_hash(), hash);
+    }
+}
@@ -71,6 +71,23 @@ pub struct BitReader<'a> {
     pos:   usize,
     src:   &'a [u8],
     mode:  BitReaderMode,
+    tail:  u8,
+    cut:   u8,
+}
+
+/// Saved bitstream reader state.
+///
+/// It is created by [`BitReader::checkpoint`] and used to return to that position with [`BitReader::restore`].
+///
+/// [`BitReader::checkpoint`]: ./struct.BitReader.html#method.checkpoint
+/// [`BitReader::restore`]: ./struct.BitReader.html#method.restore
+#[derive(Debug,Clone,Copy)]
+pub struct BitReaderCheckpoint {
+    cache: u64,
+    bits:  u8,
+    pos:   usize,
+    mode:  BitReaderMode,
+    cut:   u8,
 }
 
 #[allow(clippy::identity_op)]
@@ -87,20 +104,128 @@ impl<'a> BitReader<'a> {
     /// let mut br = BitReader::new(&bits, BitReaderMode::BE);
     /// ```
     pub fn new(src: &'a [u8], mode: BitReaderMode) -> Self {
-        BitReader{ cache: 0, pos: 0, bits: 0, src, mode }
+        BitReader{ cache: 0, pos: 0, bits: 0, src, mode, tail: 0, cut: 0 }
+    }
+
+    /// Constructs a new instance of bitstream reader that does not read past the provided number of bits.
+    ///
+    /// All reads, skips and seeks beyond the limit fail the same way as at the end of data
+    /// and [`align`] stops at the limit if the next byte boundary lies beyond it.
+    /// The limit is counted in the bitstream order so for [`LE16MSB`] and [`LE32MSB`] modes
+    /// the data is truncated to the word containing the last bit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nihav_core::io::bitreader::{BitReader,BitReaderMode};
+    ///
+    /// let bits: [u8; 4] = [ 42, 43, 44, 45 ];
+    /// let mut br = BitReader::with_limit(&bits, BitReaderMode::BE, 12);
+    /// assert!(br.read(12).is_ok());
+    /// assert!(br.read_bool().is_err());
+    /// ```
+    ///
+    /// [`align`]: #method.align
+    /// [`LE16MSB`]: ./enum.BitReaderMode.html#variant.LE16MSB
+    /// [`LE32MSB`]: ./enum.BitReaderMode.html#variant.LE32MSB
+    pub fn with_limit(src: &'a [u8], mode: BitReaderMode, limit_bits: usize) -> Self {
+        let wbits = match mode {
+                BitReaderMode::LE16MSB => 16,
+                BitReaderMode::LE32MSB => 32,
+                _ => 8,
+            };
+        let nbytes = (limit_bits.div_ceil(wbits) * wbits / 8).min(src.len());
+        let tail = (nbytes * 8).saturating_sub(limit_bits) as u8;
+        BitReader{ cache: 0, pos: 0, bits: 0, src: &src[..nbytes], mode, tail, cut: 0 }
     }
 
     /// Returns the data bitstream reader uses.
     pub fn get_data(&self) -> &'a [u8] { self.src }
 
+    /// Switches bitstream reading mode at the current position.
+    ///
+    /// The reader should be at byte boundary (or at 16-bit or 32-bit word boundary from the stream start when switching from or to [`LE16MSB`] and [`LE32MSB`] modes correspondingly), otherwise the call will return [`InvalidValue`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nihav_core::io::bitreader::{BitReader,BitReaderMode};
+    ///
+    /// # use nihav_core::io::bitreader::BitReaderResult;
+    /// # fn foo() -> BitReaderResult<u32> {
+    /// let bits: [u8; 4] = [ 42, 43, 44, 45 ];
+    /// let mut br = BitReader::new(&bits, BitReaderMode::BE);
+    /// br.skip(8)?;
+    /// br.set_mode(BitReaderMode::LE)?;
+    /// let value = br.read(16)?; // this should return 0x2C2B
+    /// # Ok(value)
+    /// # }
+    /// ```
+    ///
+    /// [`LE16MSB`]: ./enum.BitReaderMode.html#variant.LE16MSB
+    /// [`LE32MSB`]: ./enum.BitReaderMode.html#variant.LE32MSB
+    /// [`InvalidValue`]: ./enum.BitReaderError.html#variant.InvalidValue
+    pub fn set_mode(&mut self, mode: BitReaderMode) -> BitReaderResult<()> {
+        fn align_mask(mode: BitReaderMode) -> usize {
+            match mode {
+                BitReaderMode::LE16MSB => 15,
+                BitReaderMode::LE32MSB => 31,
+                _ => 7,
+            }
+        }
+        let pos = self.tell();
+        if (pos & (align_mask(self.mode) | align_mask(mode))) != 0 { return Err(InvalidValue); }
+        self.reset_cache();
+        self.pos = pos >> 3;
+        self.mode = mode;
+        Ok(())
+    }
+
     /// Reports the current bit position in the bitstream (usually simply the number of bits read so far).
     pub fn tell(&self) -> usize {
-        self.pos * 8 - (self.bits as usize)
+        self.pos * 8 - (self.bits as usize) - (self.cut as usize)
+    }
+
+    /// Saves the current reader state so the reading can be resumed from this point later.
+    ///
+    /// Unlike [`seek`] restoring a checkpoint does not need to refill the internal cache.
+    /// A checkpoint is valid only for the reader instance that created it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nihav_core::io::bitreader::{BitReader,BitReaderMode};
+    ///
+    /// # use nihav_core::io::bitreader::BitReaderResult;
+    /// # fn foo() -> BitReaderResult<()> {
+    /// let bits: [u8; 4] = [ 42, 43, 44, 45 ];
+    /// let mut br = BitReader::new(&bits, BitReaderMode::BE);
+    /// let cp = br.checkpoint();
+    /// let flag = br.read_bool()?;
+    /// if !flag { // speculative parsing failed
+    ///     br.restore(cp);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`seek`]: #method.seek
+    pub fn checkpoint(&self) -> BitReaderCheckpoint {
+        BitReaderCheckpoint { cache: self.cache, bits: self.bits, pos: self.pos, mode: self.mode, cut: self.cut }
+    }
+
+    /// Returns the reader to the state saved in the checkpoint.
+    pub fn restore(&mut self, cp: BitReaderCheckpoint) {
+        self.cache = cp.cache;
+        self.bits  = cp.bits;
+        self.pos   = cp.pos;
+        self.mode  = cp.mode;
+        self.cut   = cp.cut;
     }
 
     /// Reports the amount of bits left until the end of the bitstream.
     pub fn left(&self) -> isize {
-        ((self.src.len() as isize) - (self.pos as isize)) * 8 + (self.bits as isize)
+        ((self.src.len() as isize) - (self.pos as isize)) * 8 + (self.bits as isize) - (self.tail as isize) + (self.cut as isize)
     }
 
     fn fill32be(&mut self, src: &[u8]) {
@@ -131,9 +256,61 @@ impl<'a> BitReader<'a> {
         }
     }
 
+    // Fills the cache with as many whole bytes (or words for word-packed modes) as fit into it.
+    // Bits of the loaded word beyond the counted ones belong to the following data
+    // so they are simply set again when the cache is refilled next time.
+    #[inline(always)]
+    fn fill64(&mut self) {
+        let buf = &self.src[self.pos..][..8];
+        let nbytes = match self.mode {
+                BitReaderMode::BE => {
+                    let word = u64::from_be_bytes([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7]]);
+                    self.cache |= word >> self.bits;
+                    (64 - self.bits) >> 3
+                },
+                BitReaderMode::LE => {
+                    let word = u64::from_le_bytes([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7]]);
+                    self.cache |= word << self.bits;
+                    (64 - self.bits) >> 3
+                },
+                BitReaderMode::LE16MSB => {
+                    let word = u64::from_be_bytes([buf[1], buf[0], buf[3], buf[2], buf[5], buf[4], buf[7], buf[6]]);
+                    self.cache |= word >> self.bits;
+                    ((64 - self.bits) >> 4) * 2
+                },
+                BitReaderMode::LE32MSB => {
+                    let word = u64::from_be_bytes([buf[3], buf[2], buf[1], buf[0], buf[7], buf[6], buf[5], buf[4]]);
+                    self.cache |= word >> self.bits;
+                    ((64 - self.bits) >> 5) * 4
+                },
+            };
+        self.pos  += nbytes as usize;
+        self.bits += nbytes * 8;
+    }
+
+    // Drops the bits following the reader limit once the last data word is in the cache.
+    fn apply_limit(&mut self) {
+        if self.tail != 0 && self.cut == 0 && self.pos == self.src.len() {
+            self.bits -= self.tail;
+            self.cut = self.tail;
+            if self.bits == 0 {
+                self.cache = 0;
+            } else if let BitReaderMode::LE = self.mode {
+                self.cache &= u64::MAX >> (64 - self.bits);
+            } else {
+                self.cache &= !(u64::MAX >> self.bits);
+            }
+        }
+    }
+
     #[inline(always)]
     fn refill(&mut self) -> BitReaderResult<()> {
         if self.pos >= self.src.len() { return Err(BitstreamEnd) }
+        if self.bits <= 32 && self.pos + 8 <= self.src.len() {
+            self.fill64();
+            self.apply_limit();
+            return Ok(());
+        }
         while self.bits <= 32 {
             if self.pos + 4 <= self.src.len() {
                 let buf = &self.src[self.pos..];
@@ -165,6 +342,7 @@ impl<'a> BitReader<'a> {
                 self.bits += newbits;
             }
         }
+        self.apply_limit();
         Ok(())
     }
 
@@ -198,6 +376,7 @@ impl<'a> BitReader<'a> {
     fn reset_cache(&mut self) {
         self.bits = 0;
         self.cache = 0;
+        self.cut = 0;
     }
 
     /// Reads the specified amount of bits as an unsigned value.
@@ -237,6 +416,7 @@ impl<'a> BitReader<'a> {
 
     /// Reads the specified amount of bits as a signed value.
     ///
+    /// The value is stored in two's complement form so the top bit is sign.
     /// Beside signedness it behaves the same as [`read`].
     ///
     /// [`read`]: #method.read
@@ -251,6 +431,142 @@ impl<'a> BitReader<'a> {
         Ok(res)
     }
 
+    /// Reads the specified amount of bits (up to 64) as an unsigned value.
+    ///
+    /// Beside the maximum amount of bits it behaves the same as [`read`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nihav_core::io::bitreader::{BitReader,BitReaderMode};
+    ///
+    /// # use nihav_core::io::bitreader::BitReaderResult;
+    /// # fn foo() -> BitReaderResult<u64> {
+    /// let bits: [u8; 6] = [ 42, 43, 44, 45, 46, 47 ];
+    /// let mut br = BitReader::new(&bits, BitReaderMode::BE);
+    /// let value = br.read64(40)?; // this should return 0x2A2B2C2D2E
+    /// # Ok(value)
+    /// # }
+    /// ```
+    ///
+    /// [`read`]: #method.read
+    pub fn read64(&mut self, nbits: u8) -> BitReaderResult<u64> {
+        if nbits > 64 { return Err(TooManyBitsRequested) }
+        if nbits <= 32 {
+            return self.read(nbits).map(u64::from);
+        }
+        if self.left() < (nbits as isize) { return Err(BitstreamEnd) }
+        let res = match self.mode {
+                BitReaderMode::LE => {
+                    let lo = self.read(32)?;
+                    let hi = self.read(nbits - 32)?;
+                    (u64::from(hi) << 32) | u64::from(lo)
+                },
+                _ => {
+                    let hi = self.read(nbits - 32)?;
+                    let lo = self.read(32)?;
+                    (u64::from(hi) << 32) | u64::from(lo)
+                },
+            };
+        Ok(res)
+    }
+
+    /// Retrieves the next bits (up to 64) from the stream without advancing.
+    ///
+    /// Unlike [`peek`] it reports [`BitstreamEnd`] if there are not enough bits left in the stream.
+    ///
+    /// [`peek`]: #method.peek
+    /// [`BitstreamEnd`]: ./enum.BitReaderError.html#variant.BitstreamEnd
+    pub fn peek64(&self, nbits: u8) -> BitReaderResult<u64> {
+        let mut br = self.clone();
+        br.read64(nbits)
+    }
+
+    /// Reads a signed value stored as the specified amount of magnitude bits followed by a sign bit.
+    ///
+    /// Set sign bit means negative value, the sign bit is always present (even for zero magnitude).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nihav_core::io::bitreader::{BitReader,BitReaderMode};
+    ///
+    /// # use nihav_core::io::bitreader::BitReaderResult;
+    /// # fn foo() -> BitReaderResult<i32> {
+    /// let bits: [u8; 1] = [ 0b01011000 ];
+    /// let mut br = BitReader::new(&bits, BitReaderMode::BE);
+    /// let value = br.read_sign_magnitude(4)?; // this should return -5
+    /// # Ok(value)
+    /// # }
+    /// ```
+    pub fn read_sign_magnitude(&mut self, nbits: u8) -> BitReaderResult<i32> {
+        if nbits > 31 { return Err(TooManyBitsRequested) }
+        if self.left() < (nbits as isize) + 1 { return Err(BitstreamEnd) }
+        let mag = self.read(nbits)? as i32;
+        if self.read_bool()? {
+            Ok(-mag)
+        } else {
+            Ok(mag)
+        }
+    }
+
+    /// Reads an unsigned exponential-Golomb code.
+    ///
+    /// The code prefix may contain up to 31 zero bits so the decoded value always fits into 32 bits,
+    /// longer prefixes make the function return [`InvalidValue`].
+    /// If the stream ends in the middle of the code then [`BitstreamEnd`] is returned.
+    /// On error the reader position is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use nihav_core::io::bitreader::{BitReader,BitReaderMode};
+    ///
+    /// # use nihav_core::io::bitreader::BitReaderResult;
+    /// # fn foo() -> BitReaderResult<u32> {
+    /// let bits: [u8; 1] = [ 0b00100000 ];
+    /// let mut br = BitReader::new(&bits, BitReaderMode::BE);
+    /// let value = br.read_ue()?; // this should return 3
+    /// # Ok(value)
+    /// # }
+    /// ```
+    ///
+    /// [`InvalidValue`]: ./enum.BitReaderError.html#variant.InvalidValue
+    /// [`BitstreamEnd`]: ./enum.BitReaderError.html#variant.BitstreamEnd
+    pub fn read_ue(&mut self) -> BitReaderResult<u32> {
+        let cp = self.checkpoint();
+        let ret = self.read_ue_internal();
+        if ret.is_err() {
+            self.restore(cp);
+        }
+        ret
+    }
+
+    fn read_ue_internal(&mut self) -> BitReaderResult<u32> {
+        let mut nzeroes = 0;
+        while !self.read_bool()? {
+            nzeroes += 1;
+            if nzeroes > 31 { return Err(InvalidValue) }
+        }
+        let base = (1u64 << nzeroes) - 1;
+        Ok((base + u64::from(self.read(nzeroes)?)) as u32)
+    }
+
+    /// Reads a signed exponential-Golomb code.
+    ///
+    /// Codes are mapped to values as 0, 1, -1, 2, -2 and so on.
+    /// Errors are reported in the same way as in [`read_ue`].
+    ///
+    /// [`read_ue`]: #method.read_ue
+    pub fn read_se(&mut self) -> BitReaderResult<i32> {
+        let val = i64::from(self.read_ue()?);
+        if (val & 1) != 0 {
+            Ok(((val + 1) >> 1) as i32)
+        } else {
+            Ok((-(val >> 1)) as i32)
+        }
+    }
+
     /// Reads single bit from the stream and interprets it as a boolean value.
     #[inline(always)]
     pub fn read_bool(&mut self) -> BitReaderResult<bool> {
@@ -283,7 +599,7 @@ impl<'a> BitReader<'a> {
     /// ```
     #[inline(always)]
     pub fn peek(&mut self, nbits: u8) -> u32 {
-        if nbits > 32 { return 0 }
+        if nbits == 0 || nbits > 32 { return 0 }
         if self.bits < nbits { let _ = self.refill(); }
         self.read_cache(nbits)
     }
@@ -297,6 +613,9 @@ impl<'a> BitReader<'a> {
     /// [`BitstreamEnd`]: ./enum.BitReaderError.html#variant.BitstreamEnd
     #[inline(always)]
     pub fn skip(&mut self, nbits: u32) -> BitReaderResult<()> {
+        if self.tail != 0 && (nbits as isize) > self.left() {
+            return Err(BitstreamEnd);
+        }
         if u32::from(self.bits) >= nbits {
             self.skip_cache(nbits as u8);
             return Ok(());
@@ -304,6 +623,10 @@ impl<'a> BitReader<'a> {
         let mut skip_bits = nbits - u32::from(self.bits);
         self.reset_cache();
         self.pos += ((skip_bits / 32) * 4) as usize;
+        if self.pos > self.src.len() {
+            self.pos = self.src.len();
+            return Err(BitstreamEnd);
+        }
         skip_bits &= 0x1F;
         if skip_bits > 0 {
             self.refill()?;
@@ -316,7 +639,7 @@ impl<'a> BitReader<'a> {
     }
 
     /// Seeks to the absolute bit position in the stream.
-    /// If the requested position lies after the bitstream end the function returns [`TooManyBitsRequested`].
+    /// If the requested position lies after the bitstream end the function returns [`BitstreamEnd`] and the current position is kept.
     ///
     /// # Examples
     ///
@@ -333,9 +656,9 @@ impl<'a> BitReader<'a> {
     /// # }
     /// ```
     ///
-    /// [`TooManyBitsRequested`]: ./enum.BitReaderError.html#variant.TooManyBitsRequested
+    /// [`BitstreamEnd`]: ./enum.BitReaderError.html#variant.BitstreamEnd
     pub fn seek(&mut self, nbits: u32) -> BitReaderResult<()> {
-        if ((nbits + 7) >> 3) as usize > self.src.len() { return Err(TooManyBitsRequested); }
+        if (nbits as usize) > self.src.len() * 8 - (self.tail as usize) { return Err(BitstreamEnd); }
         self.reset_cache();
         self.pos = ((nbits / 32) * 4) as usize;
         self.skip(nbits & 0x1F)
@@ -343,6 +666,8 @@ impl<'a> BitReader<'a> {
 
     /// Aligns the bit position to the next byte boundary. If already at byte boundary the function does nothing.
     ///
+    /// For a reader created with [`with_limit`] the position never moves past the limit.
+    ///
     /// # Examples
     ///
     /// ```
@@ -358,12 +683,19 @@ impl<'a> BitReader<'a> {
     /// # Ok(())
     /// # }
     /// ```
+    ///
+    /// [`with_limit`]: #method.with_limit
     pub fn align(&mut self) {
-        let pos = self.bits & 7;
+        let pos = (self.bits + self.cut) & 7;
         if pos != 0 {
-            self.skip_cache(pos);
+            self.skip_cache(pos.min(self.bits));
         }
     }
+
+    /// Reports whether the current bit position is at byte boundary.
+    pub fn is_aligned(&self) -> bool {
+        (self.tell() & 7) == 0
+    }
 }
 
 /// Returns a variable with `len` amount of low bits in reverse order.
@@ -407,4 +739,423 @@ mod test {
         let mut br = BitReader::new(src, BitReaderMode::LE);
         assert_eq!(br.read_s(5).unwrap(), -5);
     }
+
+    #[test]
+    fn br_switch_mode() {
+        const DATA: [u8; 12] = [0xA5, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x11, 0x22, 0x33];
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        assert_eq!(br.read(4).unwrap(), 0xA);
+        assert!(br.set_mode(BitReaderMode::LE).is_err());
+        br.align();
+        assert!(br.set_mode(BitReaderMode::LE).is_ok());
+        assert_eq!(br.tell(), 8);
+        assert_eq!(br.read(4).unwrap(), 0x2);
+        assert_eq!(br.read(12).unwrap(), 0x341);
+        assert!(br.set_mode(BitReaderMode::BE).is_ok());
+        assert_eq!(br.read(8).unwrap(), 0x56);
+        assert!(br.set_mode(BitReaderMode::LE16MSB).is_ok());
+        assert_eq!(br.read(4).unwrap(), 0x9);
+        assert!(br.set_mode(BitReaderMode::BE).is_err());
+        assert_eq!(br.read(12).unwrap(), 0xA78);
+        assert!(br.set_mode(BitReaderMode::BE).is_ok());
+        assert_eq!(br.read(8).unwrap(), 0xBC);
+        assert!(br.set_mode(BitReaderMode::LE16MSB).is_err());
+        assert_eq!(br.read(8).unwrap(), 0xDE);
+        assert_eq!(br.read(8).unwrap(), 0xF0);
+        assert_eq!(br.left(), 24);
+    }
+
+    #[test]
+    fn br_read64() {
+        const DATA: [u8; 10] = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0xFE, 0xDC];
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        assert_eq!(br.peek64(33).unwrap(), 0x002468ACF);
+        assert_eq!(br.tell(), 0);
+        assert_eq!(br.read64(33).unwrap(), 0x002468ACF);
+        assert_eq!(br.read(7).unwrap(), 0x09);
+        assert_eq!(br.read64(40).unwrap(), 0xABCDEFFEDC);
+        assert!(br.peek64(1).is_err());
+
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        assert_eq!(br.read64(64).unwrap(), 0x0123456789ABCDEF);
+        assert!(br.read64(65).is_err());
+        assert!(br.read64(17).is_err());
+        assert_eq!(br.read64(16).unwrap(), 0xFEDC);
+
+        let mut br = BitReader::new(&DATA, BitReaderMode::LE);
+        assert_eq!(br.peek64(64).unwrap(), 0xEFCDAB8967452301);
+        assert_eq!(br.read64(48).unwrap(), 0xAB8967452301);
+        assert_eq!(br.read64(32).unwrap(), 0xDCFEEFCD);
+        assert_eq!(br.left(), 0);
+    }
+
+    #[test]
+    fn br_align_skip() {
+        const DATA: [u8; 20] = [0x5A; 20];
+        for start in 0..16 {
+            let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+            br.skip(start).unwrap();
+            assert_eq!(br.is_aligned(), (start & 7) == 0);
+            br.align();
+            assert!(br.is_aligned());
+            assert_eq!(br.tell(), ((start + 7) & !7) as usize);
+            assert_eq!(br.read(8).unwrap(), 0x5A);
+        }
+
+        let mut br = BitReader::new(&DATA, BitReaderMode::LE);
+        br.read(3).unwrap();
+        br.skip(120).unwrap();
+        assert_eq!(br.tell(), 123);
+        br.align();
+        assert_eq!(br.read(8).unwrap(), 0x5A);
+        assert_eq!(br.left(), 24);
+        assert!(br.skip(25).is_err());
+
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        assert!(br.skip(160).is_ok());
+        assert_eq!(br.left(), 0);
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        assert!(br.skip(192).is_err());
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        assert!(br.skip(161).is_err());
+    }
+
+    #[test]
+    fn br_signed() {
+        const DATA: [u8; 4] = [0b10011100, 0b01111111, 0b11111111, 0b11110001];
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        assert_eq!(br.read_s(1).unwrap(), -1);
+        assert_eq!(br.read_s(1).unwrap(), 0);
+        assert_eq!(br.read_s(3).unwrap(), 3);
+        assert_eq!(br.read_s(3).unwrap(), -4);
+        assert_eq!(br.read_s(4).unwrap(), 7);
+        assert_eq!(br.read_s(20).unwrap(), -15);
+        assert!(br.read_s(1).is_err());
+
+        const DATA2: [u8; 4] = [0b11100101, 0b11111111, 0b11111111, 0b00000001];
+        let mut br = BitReader::new(&DATA2, BitReaderMode::BE);
+        assert_eq!(br.read_sign_magnitude(1).unwrap(), -1);
+        assert_eq!(br.read_sign_magnitude(2).unwrap(), 2);
+        assert_eq!(br.read_sign_magnitude(3).unwrap(), -5);
+        assert_eq!(br.read_sign_magnitude(15).unwrap(), 0x7FFF);
+        assert_eq!(br.read_sign_magnitude(6).unwrap(), 0);
+        assert!(br.read_sign_magnitude(0).is_err());
+        assert!(br.read_sign_magnitude(32).is_err());
+    }
+
+    #[test]
+    fn br_exp_golomb() {
+        // codes for 0..=8
+        const DATA: [u8; 6] = [0b10100110, 0b01000010, 0b10011000, 0b11100010, 0b00000100, 0b10000000];
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        for i in 0..=8 {
+            assert_eq!(br.read_ue().unwrap(), i);
+        }
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        for &val in [0, 1, -1, 2, -2, 3, -3, 4, -4].iter() {
+            assert_eq!(br.read_se().unwrap(), val);
+        }
+
+        // the largest code allowed
+        const LONG: [u8; 8] = [0x00, 0x00, 0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFE];
+        let mut br = BitReader::new(&LONG, BitReaderMode::BE);
+        assert_eq!(br.read_ue().unwrap(), 0xFFFFFFFE);
+        let mut br = BitReader::new(&LONG, BitReaderMode::BE);
+        assert_eq!(br.read_se().unwrap(), -0x7FFFFFFF);
+
+        const TOO_LONG: [u8; 8] = [0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00];
+        let mut br = BitReader::new(&TOO_LONG, BitReaderMode::BE);
+        assert!(matches!(br.read_ue(), Err(InvalidValue)));
+        assert_eq!(br.tell(), 0);
+
+        const TRUNCATED: [u8; 1] = [0b11000010];
+        let mut br = BitReader::new(&TRUNCATED, BitReaderMode::BE);
+        assert_eq!(br.read_ue().unwrap(), 0);
+        assert_eq!(br.read_ue().unwrap(), 0);
+        assert!(matches!(br.read_ue(), Err(BitstreamEnd)));
+        assert_eq!(br.tell(), 2);
+        let mut br = BitReader::new(&[0u8; 2], BitReaderMode::BE);
+        assert!(matches!(br.read_ue(), Err(BitstreamEnd)));
+    }
+
+    #[test]
+    fn br_checkpoint() {
+        const DATA: [u8; 12] = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 0x0F, 0x1E, 0x2D, 0x3C];
+        for &mode in [BitReaderMode::BE, BitReaderMode::LE, BitReaderMode::LE16MSB, BitReaderMode::LE32MSB].iter() {
+            let mut br = BitReader::new(&DATA, mode);
+            br.skip(5).unwrap();
+            let cp = br.checkpoint();
+            let pos = br.tell();
+            let vals = [br.read(7).unwrap(), br.read(32).unwrap(), br.read(20).unwrap()];
+            br.restore(cp);
+            assert_eq!(br.tell(), pos);
+            assert_eq!([br.read(7).unwrap(), br.read(32).unwrap(), br.read(20).unwrap()], vals);
+            br.read(32).unwrap();
+            assert!(br.read_bool().is_err());
+            br.restore(cp);
+            assert_eq!(br.read(7).unwrap(), vals[0]);
+        }
+
+        let mut br = BitReader::new(&DATA, BitReaderMode::BE);
+        br.skip(16).unwrap();
+        let cp = br.checkpoint();
+        br.set_mode(BitReaderMode::LE).unwrap();
+        br.read(8).unwrap();
+        br.restore(cp);
+        assert_eq!(br.read(8).unwrap(), 0x56);
+        assert_eq!(br.read(8).unwrap(), 0x78);
+    }
+
+    // reads bits one by one following the mode definition
+    fn ref_read(src: &[u8], mode: BitReaderMode, pos: usize, nbits: u8) -> Option<u32> {
+        if pos + usize::from(nbits) > src.len() * 8 { return None; }
+        let mut val = 0;
+        for i in 0..usize::from(nbits) {
+            let bpos = pos + i;
+            let bit = match mode {
+                    BitReaderMode::BE | BitReaderMode::LE => {
+                        let shift = if let BitReaderMode::BE = mode { 7 - (bpos & 7) } else { bpos & 7 };
+                        (src[bpos >> 3] >> shift) & 1
+                    },
+                    BitReaderMode::LE16MSB => {
+                        let word = u16::from(src[(bpos >> 4) * 2]) | (u16::from(src[(bpos >> 4) * 2 + 1]) << 8);
+                        ((word >> (15 - (bpos & 15))) & 1) as u8
+                    },
+                    BitReaderMode::LE32MSB => {
+                        let off = (bpos >> 5) * 4;
+                        let word = u32::from(src[off]) | (u32::from(src[off + 1]) << 8) | (u32::from(src[off + 2]) << 16) | (u32::from(src[off + 3]) << 24);
+                        ((word >> (31 - (bpos & 31))) & 1) as u8
+                    },
+                };
+            if let BitReaderMode::LE = mode {
+                val |= u32::from(bit) << i;
+            } else {
+                val = (val << 1) | u32::from(bit);
+            }
+        }
+        Some(val)
+    }
+
+    // copy of the BitReader as it was before the 64-bit refill, it is kept as a reference for the output and speed
+    #[derive(Clone)]
+    struct Refill32Reader<'a> {
+        cache: u64,
+        bits:  u8,
+        pos:   usize,
+        src:   &'a [u8],
+        mode:  BitReaderMode,
+    }
+
+    impl<'a> Refill32Reader<'a> {
+        fn new(src: &'a [u8], mode: BitReaderMode) -> Self {
+            Self { cache: 0, bits: 0, pos: 0, src, mode }
+        }
+        fn fill32(&mut self, src: &[u8]) {
+            let nw = match self.mode {
+                    BitReaderMode::BE      => u32::from_be_bytes([src[0], src[1], src[2], src[3]]),
+                    BitReaderMode::LE16MSB => u32::from_be_bytes([src[1], src[0], src[3], src[2]]),
+                    BitReaderMode::LE |
+                    BitReaderMode::LE32MSB => u32::from_le_bytes([src[0], src[1], src[2], src[3]]),
+                };
+            if let BitReaderMode::LE = self.mode {
+                self.cache |= u64::from(nw) << self.bits;
+            } else {
+                self.cache |= u64::from(nw) << (32 - self.bits);
+            }
+        }
+        fn refill(&mut self) -> BitReaderResult<()> {
+            if self.pos >= self.src.len() { return Err(BitstreamEnd) }
+            while self.bits <= 32 {
+                if self.pos + 4 <= self.src.len() {
+                    let buf = &self.src[self.pos..];
+                    self.fill32(buf);
+                    self.pos  +=  4;
+                    self.bits += 32;
+                } else {
+                    let mut buf: [u8; 4] = [0, 0, 0, 0];
+                    let mut newbits: u8 = 0;
+                    for out in buf.iter_mut().take(3) {
+                        if self.pos < self.src.len() {
+                            *out = self.src[self.pos];
+                            self.pos += 1;
+                            newbits += 8;
+                        }
+                    }
+                    if newbits == 0 { break; }
+                    self.fill32(&buf);
+                    self.bits += newbits;
+                }
+            }
+            Ok(())
+        }
+        #[inline(always)]
+        fn read(&mut self, nbits: u8) -> BitReaderResult<u32> {
+            if nbits == 0 { return Ok(0) }
+            if nbits > 32 { return Err(TooManyBitsRequested) }
+            if self.bits < nbits {
+                self.refill()?;
+                if self.bits < nbits { return Err(BitstreamEnd) }
+            }
+            let res = match self.mode {
+                    BitReaderMode::LE => ((1u64 << nbits) - 1) & self.cache,
+                    _                 => self.cache >> (64 - nbits),
+                };
+            match self.mode {
+                BitReaderMode::LE => self.cache >>= nbits,
+                _                 => self.cache <<= nbits,
+            };
+            self.bits -= nbits;
+            Ok(res as u32)
+        }
+        fn skip(&mut self, mut nbits: u32) -> BitReaderResult<()> {
+            while nbits > 0 {
+                let len = nbits.min(32);
+                self.read(len as u8)?;
+                nbits -= len;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn br_random_reads() {
+        let mut seed = 0x1234_5678u32;
+        let mut rnd = move || { seed = seed.wrapping_mul(1664525).wrapping_add(1013904223); seed >> 8 };
+        let data: Vec<u8> = (0..4099).map(|_| rnd() as u8).collect();
+        for &mode in [BitReaderMode::BE, BitReaderMode::LE, BitReaderMode::LE16MSB, BitReaderMode::LE32MSB].iter() {
+            // word-packed modes need whole words
+            let src = match mode {
+                    BitReaderMode::BE | BitReaderMode::LE => &data[..],
+                    _ => &data[..4096],
+                };
+            let mut br = BitReader::new(src, mode);
+            let mut old_br = Refill32Reader::new(src, mode);
+            let mut pos = 0;
+            loop {
+                let op = rnd() % 8;
+                let nbits = (rnd() % 33) as u8;
+                if op == 0 {
+                    let skip = rnd() % 100;
+                    if pos + (skip as usize) > src.len() * 8 {
+                        break;
+                    }
+                    br.skip(skip).unwrap();
+                    old_br.skip(skip).unwrap();
+                    pos += skip as usize;
+                } else if op == 1 && pos + usize::from(nbits) <= src.len() * 8 {
+                    let val = br.peek(nbits);
+                    assert_eq!(Some(val), ref_read(src, mode, pos, nbits));
+                    assert_eq!(Some(val), old_br.clone().read(nbits).ok());
+                } else {
+                    let ret = br.read(nbits).ok();
+                    let rval = ref_read(src, mode, pos, nbits);
+                    assert_eq!(ret, rval);
+                    assert_eq!(ret, old_br.read(nbits).ok());
+                    if ret.is_none() {
+                        break;
+                    }
+                    pos += usize::from(nbits);
+                }
+                assert_eq!(br.tell(), pos);
+            }
+            assert!(br.left() < 100);
+        }
+    }
+
+    // compares the reading speed with the 32-bit refill reader,
+    // run with `cargo test --release -- --ignored br_refill_bench --nocapture`
+    #[test]
+    #[ignore]
+    fn br_refill_bench() {
+        use std::time::Instant;
+
+        let mut seed = 0x8765_4321u32;
+        let data: Vec<u8> = (0..1 << 20).map(|_| { seed = seed.wrapping_mul(1664525).wrapping_add(1013904223); (seed >> 24) as u8 }).collect();
+        let total_bits = data.len() * 8 - 64;
+        for &mode in [BitReaderMode::BE, BitReaderMode::LE, BitReaderMode::LE16MSB, BitReaderMode::LE32MSB].iter() {
+            let start = Instant::now();
+            let mut br = BitReader::new(&data, mode);
+            let mut sum = 0u32;
+            let mut pos = 0;
+            let mut nbits = 1;
+            while pos < total_bits {
+                sum = sum.wrapping_add(br.read(nbits).unwrap());
+                pos += usize::from(nbits);
+                nbits = nbits % 12 + 1;
+            }
+            let br_time = start.elapsed();
+
+            let start = Instant::now();
+            let mut old_br = Refill32Reader::new(&data, mode);
+            let mut old_sum = 0u32;
+            let mut pos = 0;
+            let mut nbits = 1;
+            while pos < total_bits {
+                old_sum = old_sum.wrapping_add(old_br.read(nbits).unwrap());
+                pos += usize::from(nbits);
+                nbits = nbits % 12 + 1;
+            }
+            let old_time = start.elapsed();
+
+            assert_eq!(std::hint::black_box(sum), std::hint::black_box(old_sum));
+            let mbps = |time: std::time::Duration| (data.len() as f64) / time.as_secs_f64() / 1048576.0;
+            eprintln!("{:?}: 64-bit refill {:.1} MB/s, 32-bit refill {:.1} MB/s", mode, mbps(br_time), mbps(old_time));
+        }
+    }
+
+    #[test]
+    fn br_seek_back() {
+        let data: Vec<u8> = (0..32u8).map(|i| i.wrapping_mul(37) ^ 0x5C).collect();
+        for &mode in [BitReaderMode::BE, BitReaderMode::LE, BitReaderMode::LE16MSB, BitReaderMode::LE32MSB].iter() {
+            let mut br = BitReader::new(&data, mode);
+            br.skip(13).unwrap();
+            let pos = br.tell();
+            let vals: Vec<u32> = (1..=12).map(|i| br.read(i).unwrap()).collect();
+            br.seek(pos as u32).unwrap();
+            assert_eq!(br.tell(), pos);
+            let vals2: Vec<u32> = (1..=12).map(|i| br.read(i).unwrap()).collect();
+            assert_eq!(vals, vals2);
+            assert!(matches!(br.seek(data.len() as u32 * 8 + 1), Err(BitReaderError::BitstreamEnd)));
+            assert_eq!(br.tell(), pos + 78);
+            br.seek(data.len() as u32 * 8).unwrap();
+            assert_eq!(br.left(), 0);
+        }
+    }
+
+    #[test]
+    fn br_limit() {
+        let data: Vec<u8> = (0..32u8).map(|i| i.wrapping_mul(73) ^ 0xA5).collect();
+        for &mode in [BitReaderMode::BE, BitReaderMode::LE, BitReaderMode::LE16MSB, BitReaderMode::LE32MSB].iter() {
+            for &limit in [13usize, 77, 200, 256].iter() {
+                let mut br = BitReader::with_limit(&data, mode, limit);
+                let mut pos = 0;
+                while pos + 5 <= limit {
+                    assert_eq!(br.read(5).ok(), ref_read(&data, mode, pos, 5));
+                    pos += 5;
+                    assert_eq!(br.tell(), pos);
+                    assert_eq!(br.left(), (limit - pos) as isize);
+                }
+                let rest = (limit - pos) as u8;
+                let rval = ref_read(&data, mode, pos, rest).unwrap();
+                let pval = if let BitReaderMode::LE = mode { rval } else { rval << 3 };
+                assert_eq!(br.peek(rest + 3), pval);
+                assert_eq!(br.read(rest).unwrap(), rval);
+                assert_eq!(br.tell(), limit);
+                assert_eq!(br.left(), 0);
+                assert!(br.read_bool().is_err());
+
+                assert!(matches!(br.seek(limit as u32 + 1), Err(BitReaderError::BitstreamEnd)));
+                br.seek(limit as u32 - 3).unwrap();
+                assert_eq!(br.read(3).unwrap(), ref_read(&data, mode, limit - 3, 3).unwrap());
+                br.seek(0).unwrap();
+                assert!(br.skip(limit as u32 + 1).is_err());
+                br.seek(0).unwrap();
+                br.skip(limit as u32).unwrap();
+                assert_eq!(br.left(), 0);
+                br.seek(limit as u32 - 3).unwrap();
+                br.align();
+                assert_eq!(br.tell(), ((limit - 3 + 7) & !7).min(limit));
+            }
+        }
+    }
 }
@@ -0,0 +1,207 @@
+//! Bitstream writer functionality.
+//!
+//! Bitstream writer outputs bits into `Vec<u8>` in the same modes ([`BitReaderMode`]) as the [`BitReader`] reads them.
+//!
+//! # Examples
+//!
+//! Writing some bits and reading them back:
+//! ```
+//! use nihav_core::io::bitwriter::BitWriter;
+//! use nihav_core::io::bitreader::{BitReader,BitReaderMode};
+//!
+//! let mut bw = BitWriter::new(Vec::new(), BitReaderMode::BE);
+//! bw.write(42, 7);
+//! bw.write_bit(true);
+//! let data = bw.into_bytes();
+//! let mut br = BitReader::new(&data, BitReaderMode::BE);
+//! assert_eq!(br.read(7).unwrap(), 42);
+//! assert!(br.read_bool().unwrap());
+//! ```
+//!
+//! [`BitReader`]: ../bitreader/struct.BitReader.html
+//! [`BitReaderMode`]: ../bitreader/enum.BitReaderMode.html
+
+use crate::io::bitreader::BitReaderMode;
+
+fn word_bits(mode: BitReaderMode) -> u8 {
+    match mode {
+        BitReaderMode::LE16MSB => 16,
+        BitReaderMode::LE32MSB => 32,
+        _ => 8,
+    }
+}
+
+/// Bitstream writer.
+#[derive(Debug,Clone)]
+pub struct BitWriter {
+    dst:    Vec<u8>,
+    cache:  u64,
+    bits:   u8,
+    mode:   BitReaderMode,
+}
+
+impl BitWriter {
+    /// Constructs a new instance of bitstream writer that appends data to the provided buffer.
+    pub fn new(dst: Vec<u8>, mode: BitReaderMode) -> Self {
+        Self { dst, cache: 0, bits: 0, mode }
+    }
+    /// Reports the current bit position in the output (including the initial buffer contents).
+    pub fn tell(&self) -> usize {
+        self.dst.len() * 8 + (self.bits as usize)
+    }
+    /// Writes the lowest `nbits` of the provided value (up to 32 bits).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if more than 32 bits are requested.
+    pub fn write(&mut self, val: u32, nbits: u8) {
+        assert!(nbits <= 32);
+        if nbits == 0 {
+            return;
+        }
+        let val = u64::from(val) & ((1u64 << nbits) - 1);
+        if let BitReaderMode::LE = self.mode {
+            self.cache |= val << self.bits;
+            self.bits += nbits;
+            while self.bits >= 8 {
+                self.dst.push(self.cache as u8);
+                self.cache >>= 8;
+                self.bits -= 8;
+            }
+        } else {
+            self.cache = (self.cache << nbits) | val;
+            self.bits += nbits;
+            let wbits = word_bits(self.mode);
+            while self.bits >= wbits {
+                self.bits -= wbits;
+                let word = (self.cache >> self.bits) as u32;
+                self.put_word(word);
+                self.cache &= (1u64 << self.bits) - 1;
+            }
+        }
+    }
+    /// Writes the lowest `nbits` of the provided value (up to 64 bits).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if more than 64 bits are requested.
+    pub fn write64(&mut self, val: u64, nbits: u8) {
+        assert!(nbits <= 64);
+        if nbits <= 32 {
+            self.write(val as u32, nbits);
+        } else if let BitReaderMode::LE = self.mode {
+            self.write(val as u32, 32);
+            self.write((val >> 32) as u32, nbits - 32);
+        } else {
+            self.write((val >> 32) as u32, nbits - 32);
+            self.write(val as u32, 32);
+        }
+    }
+    /// Writes the lowest `nbits` of the provided signed value in two's complement form.
+    pub fn write_s(&mut self, val: i32, nbits: u8) {
+        self.write(val as u32, nbits);
+    }
+    /// Writes a single bit.
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write(bit as u32, 1);
+    }
+    /// Pads the output with zero bits up to the next byte boundary.
+    ///
+    /// For modes packing bits into 16- or 32-bit words the partial word is still kept until it is filled or the writer is finalised.
+    pub fn align(&mut self) {
+        let pad = (8 - (self.bits & 7)) & 7;
+        self.write(0, pad);
+    }
+    /// Finalises the bitstream and returns the output buffer.
+    ///
+    /// The output is padded with zero bits to the byte or word (for [`LE16MSB`] and [`LE32MSB`] modes) boundary.
+    ///
+    /// [`LE16MSB`]: ../bitreader/enum.BitReaderMode.html#variant.LE16MSB
+    /// [`LE32MSB`]: ../bitreader/enum.BitReaderMode.html#variant.LE32MSB
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        let wbits = word_bits(self.mode);
+        if self.bits > 0 {
+            let pad = wbits - self.bits;
+            self.write(0, pad);
+        }
+        self.dst
+    }
+
+    fn put_word(&mut self, word: u32) {
+        match self.mode {
+            BitReaderMode::BE => self.dst.push(word as u8),
+            BitReaderMode::LE16MSB => {
+                self.dst.push(word as u8);
+                self.dst.push((word >> 8) as u8);
+            },
+            BitReaderMode::LE32MSB => {
+                self.dst.push(word as u8);
+                self.dst.push((word >> 8) as u8);
+                self.dst.push((word >> 16) as u8);
+                self.dst.push((word >> 24) as u8);
+            },
+            BitReaderMode::LE => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::bitreader::*;
+
+    struct Lcg(u32);
+    impl Lcg {
+        fn next(&mut self) -> u32 {
+            self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+            self.0
+        }
+    }
+
+    #[test]
+    fn bw_roundtrip() {
+        for &mode in [BitReaderMode::BE, BitReaderMode::LE, BitReaderMode::LE16MSB, BitReaderMode::LE32MSB].iter() {
+            let mut rng = Lcg(42);
+            let mut fields = Vec::new();
+            let mut bw = BitWriter::new(Vec::new(), mode);
+            for _ in 0..1000 {
+                let nbits = (rng.next() >> 16) % 65;
+                let val = (u64::from(rng.next()) << 32) | u64::from(rng.next());
+                let val = if nbits < 64 { val & ((1u64 << nbits) - 1) } else { val };
+                bw.write64(val, nbits as u8);
+                fields.push((val, nbits as u8));
+            }
+            let nbits = bw.tell();
+            let data = bw.into_bytes();
+            assert_eq!(data.len() % usize::from(word_bits(mode) / 8), 0);
+            assert!(data.len() * 8 >= nbits);
+            let mut br = BitReader::new(&data, mode);
+            for &(val, nbits) in fields.iter() {
+                assert_eq!(br.read64(nbits).unwrap(), val);
+            }
+            assert_eq!(br.tell(), nbits);
+        }
+    }
+
+    #[test]
+    fn bw_align() {
+        let mut bw = BitWriter::new(vec![0xFF], BitReaderMode::BE);
+        bw.write(0b101, 3);
+        bw.align();
+        assert_eq!(bw.tell(), 16);
+        bw.align();
+        bw.write_s(-2, 4);
+        bw.write_bit(true);
+        assert_eq!(bw.into_bytes(), vec![0xFF, 0b10100000, 0b11101000]);
+
+        let mut bw = BitWriter::new(Vec::new(), BitReaderMode::LE);
+        bw.write(0b101, 3);
+        bw.align();
+        bw.write(0x1234, 16);
+        assert_eq!(bw.into_bytes(), vec![0b101, 0x34, 0x12]);
+
+        let mut bw = BitWriter::new(Vec::new(), BitReaderMode::LE16MSB);
+        bw.write(0xAB, 8);
+        assert_eq!(bw.into_bytes(), vec![0x00, 0xAB]);
+    }
+}
@@ -2,6 +2,8 @@ use nihav_core::codecs::*;
 use nihav_core::io::bitreader::*;
 use nihav_codec_support::codecs::{MV, ZERO_MV};
 use super::vpcommon::*;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::{Duration, Instant};
 
 pub const TOKEN_LARGE: u8 = 5;
 pub const TOKEN_EOB: u8 = 42;
@@ -79,7 +81,7 @@ fn prob2weight(a: u8, b: u8) -> u8 {
 }
 
 impl VP6Huff {
-    fn build_codes(&mut self, probs: &[u8; 11]) {
+    pub fn build_codes(&mut self, probs: &[u8; 11]) {
         let mut weights = [0u8; 12];
 
         weights[11] = prob2weight( probs[0],  probs[ 1]);
@@ -105,7 +107,7 @@ impl VP6Huff {
 
         self.build(&weights);
     }
-    fn build_codes_zero_run(&mut self, probs: &[u8; 14]) {
+    pub fn build_codes_zero_run(&mut self, probs: &[u8; 14]) {
         let mut weights = [0u8; 9];
 
         let root   = prob2weight( probs[0],  probs[1]);
@@ -342,6 +344,111 @@ impl VP56DCPred {
     }
 }
 
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct VP56FrameStats {
+    pub quant:          u8,
+    pub intra_mbs:      usize,
+    pub inter_mbs:      usize,
+    // the golden frame was replaced by this frame (updates skipped by set_ignore_golden_updates() are not reported)
+    pub golden_update:  bool,
+    // macroblocks replaced by error concealment (in colour and alpha planes)
+    pub concealed_mbs:  usize,
+}
+
+// totals for the frames decoded since the decoder creation or the last flush();
+// only frames actually decoded from the stream are counted (errors and missing reference fallbacks are not)
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct VP56SessionStats {
+    pub frames:         usize,
+    pub keyframes:      usize,
+    // input size of the counted frames
+    pub bytes:          usize,
+    // sum of frame quantisers, see avg_quant()
+    pub quant_sum:      u64,
+    // total decoding time, it is collected only while timing is enabled
+    pub decode_time:    Duration,
+}
+
+impl VP56SessionStats {
+    pub fn avg_quant(&self) -> Option<f32> {
+        if self.frames > 0 {
+            Some((self.quant_sum as f32) / (self.frames as f32))
+        } else {
+            None
+        }
+    }
+}
+
+// motion information for a macroblock, the vector is in quarter-pel luma units
+// (for macroblocks with four motion vectors it is the vector of the last block);
+// reference is 0 for intra macroblocks, VP_REF_INTER or VP_REF_GOLDEN otherwise
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct VP56MBMotion {
+    pub mv:             MV,
+    pub ref_id:         u8,
+}
+
+// time spent on decoding the last frame;
+// entropy decoding and reconstruction are interleaved per macroblock so they are measured together
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct VP56Timing {
+    // frame header and model parsing plus frame setup
+    pub parse:          Duration,
+    // macroblock decoding and reconstruction (for colour and alpha planes together)
+    pub mbs:            Duration,
+    // postprocessing and output format conversion
+    pub postproc:       Duration,
+    // whole decode_frame() time, equal to the sum of the other fields
+    pub total:          Duration,
+}
+
+fn time_since(start: Option<Instant>) -> Duration {
+    start.map_or(Duration::ZERO, |t| t.elapsed())
+}
+
+// copies picture data between frames with the same format and dimensions but possibly different strides
+fn vp_copy_frame(src: &NAVideoBuffer<u8>, dst: &mut NAVideoBuffer<u8>) {
+    let fmt = src.get_info().get_format();
+    let nplanes = if fmt.is_unpacked() { fmt.get_num_comp() } else { 1 };
+    for plane in 0..nplanes {
+        let (w, h) = if fmt.is_unpacked() {
+                src.get_dimensions(plane)
+            } else {
+                (src.get_info().get_width() * usize::from(fmt.get_elem_size()), src.get_info().get_height())
+            };
+        let sstride = src.get_stride(plane);
+        let dstride = dst.get_stride(plane);
+        let soff = src.get_offset(plane);
+        let doff = dst.get_offset(plane);
+        let ddata = dst.get_data_mut().unwrap();
+        for (dline, sline) in ddata[doff..].chunks_mut(dstride).zip(src.get_data()[soff..].chunks(sstride)).take(h) {
+            dline[..w].copy_from_slice(&sline[..w]);
+        }
+    }
+}
+
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub enum VP56MissingRef {
+    // report missing reference frames as an error
+    #[default]
+    Error,
+    // repeat the last successfully decoded frame until the next keyframe
+    Last,
+    // output a mid-gray frame of the current dimensions until the next keyframe
+    // (this only keeps the output going, the missing data is not recovered)
+    Gray,
+}
+
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub enum VP56OutputFormat {
+    // native YUV 4:2:0 output (with alpha plane for VP6A)
+    #[default]
+    YUV,
+    // packed RGB converted by the decoder, alpha is discarded;
+    // it is convenient for simple display paths but takes an additional allocation and conversion per frame
+    RGB24,
+}
+
 pub struct VP56Decoder {
     version:    u8,
     has_alpha:  bool,
@@ -349,6 +456,8 @@ pub struct VP56Decoder {
     shuf:       VPShuffler,
     width:      usize,
     height:     usize,
+    disp_w:     usize,
+    disp_h:     usize,
     mb_w:       usize,
     mb_h:       usize,
     models:     VP56Models,
@@ -367,6 +476,24 @@ pub struct VP56Decoder {
     top_ctx:    [Vec<u8>; 4],
 
     mc_buf:     NAVideoBufferRef<u8>,
+
+    calc_fp:    bool,
+    fingerprint: Option<u64>,
+    postproc:   u8,
+    miss_ref:   VP56MissingRef,
+    last_out:   Option<NABufferType>,
+    out_fmt:    VP56OutputFormat,
+    stats:      VP56FrameStats,
+    session:    VP56SessionStats,
+    luma_only:  bool,
+    no_golden:  bool,
+    conceal:    bool,
+    eof:        bool,
+    timing_on:  bool,
+    export_mv:  bool,
+    motion:     Vec<VP56MBMotion>,
+    timing:     VP56Timing,
+    max_dims:   Option<(usize, usize)>,
 }
 
 fn rescale_mb_mode_prob(prob: u32, total: u32) -> u8 {
@@ -432,6 +559,8 @@ impl VP56Decoder {
             shuf:       VPShuffler::new(),
             width:      0,
             height:     0,
+            disp_w:     0,
+            disp_h:     0,
             mb_w:       0,
             mb_h:       0,
             models:     VP56Models::new(),
@@ -450,6 +579,146 @@ impl VP56Decoder {
             top_ctx:    [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
 
             mc_buf,
+
+            calc_fp:    false,
+            fingerprint: None,
+            postproc:   0,
+            miss_ref:   VP56MissingRef::default(),
+            last_out:   None,
+            out_fmt:    VP56OutputFormat::default(),
+            stats:      VP56FrameStats::default(),
+            session:    VP56SessionStats::default(),
+            luma_only:  false,
+            no_golden:  false,
+            conceal:    false,
+            eof:        false,
+            timing_on:  false,
+            export_mv:  false,
+            motion:     Vec::new(),
+            timing:     VP56Timing::default(),
+            max_dims:   None,
+        }
+    }
+    pub fn set_fingerprinting(&mut self, enable: bool) {
+        self.calc_fp = enable;
+        self.fingerprint = None;
+    }
+    pub fn get_fingerprint(&self) -> Option<u64> { self.fingerprint }
+    // see vp_postprocess() for the meaning of the levels, values above the maximum are clipped
+    pub fn set_postproc_level(&mut self, level: u8) {
+        self.postproc = level.min(VP_MAX_POSTPROC_LEVEL);
+    }
+    pub fn set_missing_ref_mode(&mut self, mode: VP56MissingRef) {
+        self.miss_ref = mode;
+        if mode != VP56MissingRef::Last {
+            self.last_out = None;
+        }
+    }
+    pub fn set_output_format(&mut self, fmt: VP56OutputFormat) {
+        self.out_fmt = fmt;
+        self.last_out = None;
+    }
+    // selects RGB24 output if it is requested and native output otherwise,
+    // returns the format of the frames the decoder will output (only the pixel format of the request is considered)
+    pub fn negotiate_output_format(&mut self, vinfo: NAVideoInfo) -> NAVideoInfo {
+        let fmt = if vinfo.get_format() == RGB24_FORMAT { VP56OutputFormat::RGB24 } else { VP56OutputFormat::YUV };
+        self.set_output_format(fmt);
+        NAVideoInfo::new(self.width, self.height, self.flip, self.output_pix_fmt())
+    }
+    fn output_pix_fmt(&self) -> NAPixelFormaton {
+        match self.out_fmt {
+            VP56OutputFormat::RGB24 => RGB24_FORMAT,
+            VP56OutputFormat::YUV if self.has_alpha => VP_YUVA420_FORMAT,
+            VP56OutputFormat::YUV => YUV420_FORMAT,
+        }
+    }
+    pub fn get_frame_stats(&self) -> VP56FrameStats { self.stats }
+    pub fn get_session_stats(&self) -> VP56SessionStats { self.session }
+    // chroma planes are not reconstructed and set to neutral grey instead;
+    // since reference frames lack chroma as well, disabling it gives correct colours only from the next keyframe
+    pub fn set_luma_only(&mut self, enable: bool) {
+        self.luma_only = enable;
+    }
+    // when enabled, golden frame updates signalled by inter frames are not applied and the old golden frame is kept
+    // (keyframes still replace it); this makes golden references drift visibly so it is suitable only for preview or scrubbing
+    pub fn set_ignore_golden_updates(&mut self, enable: bool) {
+        self.no_golden = enable;
+    }
+    // when enabled, the frame is returned reconstructed up to the macroblock where an error in the macroblock data occurred
+    // (InvalidData or ShortData) with the rest of it copied from the previous frame (or grey if there is none);
+    // errors in the frame header, missing references and allocation failures are still reported.
+    pub fn set_error_concealment(&mut self, enable: bool) {
+        self.conceal = enable;
+    }
+    // motion export does not affect decoding, it merely keeps a copy of the macroblock motion information
+    pub fn set_motion_export(&mut self, enable: bool) {
+        self.export_mv = enable;
+        self.motion.clear();
+    }
+    // returns motion information for the last decoded frame as a grid of macroblocks in raster order
+    // along with its width and height in macroblocks
+    pub fn get_motion_vectors(&self) -> Option<(usize, usize, &[VP56MBMotion])> {
+        if self.export_mv && !self.motion.is_empty() {
+            Some((self.mb_w, self.mb_h, &self.motion))
+        } else {
+            None
+        }
+    }
+    fn export_motion(&mut self) {
+        self.motion.clear();
+        self.motion.extend(self.mb_info.iter().map(|mb| VP56MBMotion { mv: mb.mv, ref_id: mb.mb_type.get_ref_id() }));
+    }
+    // timing is not collected by default so there is no overhead of querying the clock
+    pub fn set_timing(&mut self, enable: bool) {
+        self.timing_on = enable;
+        self.timing = VP56Timing::default();
+    }
+    // the values are valid only after a frame has been successfully decoded
+    pub fn get_timing(&self) -> VP56Timing { self.timing }
+    fn timer_start(&self) -> Option<Instant> {
+        if self.timing_on { Some(Instant::now()) } else { None }
+    }
+    // frames larger than the provided limits are rejected before any buffers get allocated
+    pub fn set_max_dimensions(&mut self, max_dims: Option<(usize, usize)>) {
+        self.max_dims = max_dims;
+    }
+    fn check_dimensions(&self, width: usize, height: usize) -> DecoderResult<()> {
+        if let Some((max_w, max_h)) = self.max_dims {
+            validate!(width <= max_w && height <= max_h);
+        }
+        Ok(())
+    }
+    fn missing_ref(&self) -> DecoderResult<(NABufferType, FrameType)> {
+        match (self.miss_ref, self.last_out.as_ref()) {
+            (VP56MissingRef::Last, Some(buf)) => Ok((buf.clone(), FrameType::Skip)),
+            (VP56MissingRef::Gray, _) => {
+                let fmt = if !self.has_alpha { YUV420_FORMAT } else { VP_YUVA420_FORMAT };
+                let vinfo = NAVideoInfo::new(self.width, self.height, self.flip, fmt);
+                let mut buf = alloc_video_buffer(vinfo, 4)?.get_vbuf().ok_or(DecoderError::Bug)?;
+                for (plane, (_, data, _)) in buf.planes_mut().enumerate() {
+                    let fill = if plane == 3 { 255 } else { 128 };
+                    for el in data.iter_mut() {
+                        *el = fill;
+                    }
+                }
+                if self.disp_w < self.width || self.disp_h < self.height {
+                    buf.set_crop_rect(Some((0, 0, self.disp_w, self.disp_h)));
+                }
+                Ok((self.convert_output(buf)?, FrameType::Skip))
+            },
+            _ => Err(DecoderError::MissingReference),
+        }
+    }
+    fn convert_output(&self, buf: NAVideoBufferRef<u8>) -> DecoderResult<NABufferType> {
+        match self.out_fmt {
+            VP56OutputFormat::YUV => Ok(NABufferType::Video(buf)),
+            VP56OutputFormat::RGB24 => {
+                let rgb_info = NAVideoInfo::new(self.width, self.height, self.flip, self.output_pix_fmt());
+                let mut rgb = alloc_video_buffer(rgb_info, 0)?.get_vbuf().ok_or(DecoderError::Bug)?;
+                vp_yuv2rgb(&buf, &mut rgb);
+                rgb.set_crop_rect(buf.get_crop_rect());
+                Ok(NABufferType::VideoPacked(rgb))
+            },
         }
     }
     fn set_dimensions(&mut self, width: usize, height: usize) {
@@ -459,36 +728,115 @@ impl VP56Decoder {
         self.mb_h   = (self.height + 15) >> 4;
         self.mb_info.resize(self.mb_w * self.mb_h, MBInfo::default());
         self.top_ctx = [vec![0; self.mb_w * 2], vec![0; self.mb_w], vec![0; self.mb_w], vec![0; self.mb_w * 2]];
+        self.dc_pred.resize(self.mb_w);
+    }
+    // current, previous and golden frames (plus a golden frame copy for alpha streams),
+    // a frame for the postprocessed output and a frame for the last output kept for missing references
+    fn pool_frames(&self, has_alpha: bool) -> usize {
+        3 + (has_alpha as usize) + ((self.postproc > 0) as usize) + ((self.miss_ref == VP56MissingRef::Last) as usize)
+    }
+    // postprocessing and the missing reference mode may have been changed after init()
+    fn reserve_output_frames(&self, supp: &mut NADecoderSupport, vinfo: NAVideoInfo) -> DecoderResult<()> {
+        supp.pool_u8.set_dec_bufs(self.pool_frames(self.has_alpha));
+        supp.pool_u8.prealloc_video(vinfo, 4)?;
+        Ok(())
     }
     pub fn init(&mut self, supp: &mut NADecoderSupport, vinfo: NAVideoInfo) -> DecoderResult<()> {
-        supp.pool_u8.set_dec_bufs(3 + if vinfo.get_format().has_alpha() { 1 } else { 0 });
+        self.check_dimensions(vinfo.get_width(), vinfo.get_height())?;
+        supp.pool_u8.set_dec_bufs(self.pool_frames(vinfo.get_format().has_alpha()));
         supp.pool_u8.prealloc_video(NAVideoInfo::new(vinfo.get_width(), vinfo.get_height(), false, vinfo.get_format()), 4)?;
         self.set_dimensions(vinfo.get_width(), vinfo.get_height());
-        self.dc_pred.resize(self.mb_w);
+        self.disp_w = self.width;
+        self.disp_h = self.height;
         Ok(())
     }
     pub fn flush(&mut self) {
         self.shuf.clear();
+        self.last_out = None;
+        self.session = VP56SessionStats::default();
+    }
+    // cheap best-effort check that the packet looks decodable, only frame headers are parsed
+    // (using a copy of the parser so neither the parser nor the decoder state is changed)
+    pub fn probe<P: VP56Parser + Clone>(&self, src: &[u8], br: &P) -> DecoderResult<bool> {
+        let mut br = br.clone();
+        let aoffset;
+        let mut parts = Vec::with_capacity(2);
+        if self.has_alpha {
+            if src.len() < 7 {
+                return Ok(false);
+            }
+            aoffset = ((src[0] as usize) << 16) | ((src[1] as usize) << 8) | (src[2] as usize);
+            if aoffset <= 3 || aoffset >= src.len() - 3 {
+                return Ok(false);
+            }
+            parts.push((&src[3..], aoffset));
+            parts.push((&src[aoffset + 3..], src.len() - aoffset - 3));
+        } else {
+            if src.len() < 4 {
+                return Ok(false);
+            }
+            parts.push((src, src.len()));
+        }
+        let (mut mb_w, mut mb_h) = (self.mb_w, self.mb_h);
+        for (i, &(part, part_len)) in parts.iter().enumerate() {
+            let hdr = match BoolCoder::new(part).and_then(|mut bc| br.parse_header(&mut bc)) {
+                    Ok(hdr) => hdr,
+                    Err(_) => return Ok(false),
+                };
+            if (hdr.offset as usize) >= part_len {
+                return Ok(false);
+            }
+            if !hdr.is_intra && !self.shuf.has_refs() {
+                return Ok(false);
+            }
+            if hdr.mb_w != 0 {
+                if i == 0 {
+                    mb_w = usize::from(hdr.mb_w);
+                    mb_h = usize::from(hdr.mb_h);
+                    if self.check_dimensions(mb_w * 16, mb_h * 16).is_err() {
+                        return Ok(false);
+                    }
+                } else if usize::from(hdr.mb_w) != mb_w || usize::from(hdr.mb_h) != mb_h {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
     }
     pub fn decode_frame(&mut self, supp: &mut NADecoderSupport, src: &[u8], br: &mut dyn VP56Parser) -> DecoderResult<(NABufferType, FrameType)> {
+        self.decode_frame_int(supp, src, br, None)
+    }
+    // if the destination buffer is provided the output picture is written there and NABufferType::None is returned
+    // (except for the substitute frames produced for missing references)
+    fn decode_frame_int(&mut self, supp: &mut NADecoderSupport, src: &[u8], br: &mut dyn VP56Parser, dst: Option<&mut NAVideoBuffer<u8>>) -> DecoderResult<(NABufferType, FrameType)> {
+        let frame_start = self.timer_start();
+        self.timing = VP56Timing::default();
         let aoffset;
         let mut bc;
         if self.has_alpha {
             validate!(src.len() >= 7);
             aoffset = ((src[0] as usize) << 16) | ((src[1] as usize) << 8) | (src[2] as usize);
-            validate!((aoffset > 0) && (aoffset < src.len() - 3));
+            validate!((aoffset > 3) && (aoffset < src.len() - 3));
             bc = BoolCoder::new(&src[3..])?;
         } else {
             validate!(src.len() >= 4);
             aoffset = src.len();
             bc = BoolCoder::new(src)?;
         }
-        let hdr = br.parse_header(&mut bc)?;
+        let hdr = match br.parse_header(&mut bc) {
+                Err(DecoderError::MissingReference) => return self.missing_ref(),
+                ret => ret?,
+            };
         validate!((hdr.offset as usize) < aoffset); //XXX: take alpha 3 byte offset into account?
 
         if hdr.mb_w != 0 && (usize::from(hdr.mb_w) != self.mb_w || usize::from(hdr.mb_h) != self.mb_h) {
+            self.check_dimensions((hdr.mb_w as usize) * 16, (hdr.mb_h as usize) * 16)?;
             self.set_dimensions((hdr.mb_w as usize) * 16, (hdr.mb_h as usize) * 16);
         }
+        if hdr.mb_w != 0 {
+            self.disp_w = if hdr.disp_w != 0 { (hdr.disp_w as usize) * 16 } else { self.width };
+            self.disp_h = if hdr.disp_h != 0 { (hdr.disp_h as usize) * 16 } else { self.height };
+        }
         let fmt = if !self.has_alpha {
                 YUV420_FORMAT
             } else {
@@ -510,45 +858,84 @@ impl VP56Decoder {
             }
             buf = ret.unwrap();
         }
+        if self.disp_w < self.width || self.disp_h < self.height {
+            buf.set_crop_rect(Some((0, 0, self.disp_w, self.disp_h)));
+        } else {
+            buf.set_crop_rect(None);
+        }
         let mut dframe = NASimpleVideoFrame::from_video_buf(&mut buf).unwrap();
+        if self.luma_only {
+            for plane in 1..3 {
+                let off = dframe.offset[plane];
+                let stride = dframe.stride[plane];
+                let (w, h) = (dframe.width[plane], dframe.height[plane]);
+                for line in dframe.data[off..].chunks_mut(stride).take(h) {
+                    for el in line[..w].iter_mut() {
+                        *el = 128;
+                    }
+                }
+            }
+        }
 
         if hdr.is_intra {
-            self.shuf.clear();
+            // alpha stream may still refer to the previous frame
+            if !self.has_alpha {
+                self.shuf.clear();
+            }
         } else {
             if !self.shuf.has_refs() {
-                return Err(DecoderError::MissingReference);
+                return self.missing_ref();
             }
         }
 
         let psrc = &src[if self.has_alpha { 3 } else { 0 }..aoffset];
-        self.decode_planes(br, &mut dframe, &mut bc, &hdr, psrc, false)?;
+        let concealed_mbs = self.decode_planes(br, &mut dframe, &mut bc, &hdr, psrc, false)?;
+
+        let golden = hdr.is_golden && (hdr.is_intra || !self.no_golden);
+        let intra_mbs = self.mb_info.iter().filter(|mb| mb.mb_type.is_intra()).count();
+        self.stats = VP56FrameStats {
+                quant:          hdr.quant,
+                intra_mbs,
+                inter_mbs:      self.mb_info.len() - intra_mbs,
+                golden_update:  golden,
+                concealed_mbs,
+            };
+        if self.export_mv {
+            self.export_motion();
+        }
 
         if self.has_alpha {
             let asrc = &src[aoffset + 3..];
             let mut bc = BoolCoder::new(asrc)?;
             let ahdr = br.parse_header(&mut bc)?;
-            validate!(ahdr.mb_w == hdr.mb_w && ahdr.mb_h == hdr.mb_h);
+            if ahdr.mb_w != 0 {
+                validate!(usize::from(ahdr.mb_w) == self.mb_w && usize::from(ahdr.mb_h) == self.mb_h);
+            }
+            if !ahdr.is_intra && !self.shuf.has_refs() {
+                return self.missing_ref();
+            }
             std::mem::swap(&mut self.models, &mut self.amodels);
             let ret = self.decode_planes(br, &mut dframe, &mut bc, &ahdr, asrc, true);
             std::mem::swap(&mut self.models, &mut self.amodels);
-            ret?;
-            match (hdr.is_golden, ahdr.is_golden) {
+            self.stats.concealed_mbs += ret?;
+            let agolden = ahdr.is_golden && (ahdr.is_intra || !self.no_golden);
+            match (golden, agolden) {
                 (true, true) => { self.shuf.add_golden_frame(buf.clone()); },
                 (true, false) => {
-                    let cur_golden = self.shuf.get_golden().unwrap();
+                    let cur_golden = self.shuf.get_golden().ok_or(DecoderError::MissingReference)?;
                     let off    = cur_golden.get_offset(3);
                     let stride = cur_golden.get_stride(3);
-                    let mut new_golden = supp.pool_u8.get_copy(&buf).unwrap();
+                    let mut new_golden = supp.pool_u8.get_copy(&buf).ok_or(DecoderError::AllocError)?;
                     let dst = new_golden.get_data_mut().unwrap();
                     let src = cur_golden.get_data();
                     dst[off..][..stride * self.mb_h * 16].copy_from_slice(&src[off..][..stride * self.mb_h * 16]);
                     self.shuf.add_golden_frame(new_golden);
                 },
                 (false, true) => {
-                    let cur_golden = self.shuf.get_golden().unwrap();
+                    let cur_golden = self.shuf.get_golden().ok_or(DecoderError::MissingReference)?;
                     let off    = cur_golden.get_offset(3);
                     let stride = cur_golden.get_stride(3);
-                    let mut new_golden = supp.pool_u8.get_copy(&cur_golden).unwrap();
+                    let mut new_golden = supp.pool_u8.get_copy(&cur_golden).ok_or(DecoderError::AllocError)?;
                     let dst = new_golden.get_data_mut().unwrap();
                     let src = buf.get_data();
                     dst[off..][..stride * self.mb_h * 16].copy_from_slice(&src[off..][..stride * self.mb_h * 16]);
@@ -558,17 +945,134 @@ impl VP56Decoder {
             };
         }
 
-        if hdr.is_golden && !self.has_alpha {
+        if golden && !self.has_alpha {
             self.shuf.add_golden_frame(buf.clone());
         }
         self.shuf.add_frame(buf.clone());
 
-        Ok((NABufferType::Video(buf), if hdr.is_intra { FrameType::I } else { FrameType::P }))
+        let pp_start = self.timer_start();
+        let loop_str = i16::from(VP56_FILTER_LIMITS[hdr.quant as usize]);
+        if self.postproc > 0 || self.miss_ref == VP56MissingRef::Last {
+            self.reserve_output_frames(supp, vinfo)?;
+        }
+        let out = if let Some(dst) = dst {
+                self.output_into(supp, &buf, loop_str, dst)?;
+                NABufferType::None
+            } else {
+                // postprocessing is done on a copy since the decoded frame serves as a reference
+                if self.postproc > 0 {
+                    let mut pbuf = supp.pool_u8.get_copy(&buf).ok_or(DecoderError::AllocError)?;
+                    vp_postprocess(&mut pbuf, self.postproc, loop_str);
+                    buf = pbuf;
+                }
+
+                self.fingerprint = if self.calc_fp { Some(vp_fingerprint(&buf)) } else { None };
+                let out = self.convert_output(buf)?;
+                if self.miss_ref == VP56MissingRef::Last {
+                    self.last_out = Some(out.clone());
+                }
+                out
+            };
+        if self.timing_on {
+            self.timing.postproc = time_since(pp_start);
+            self.timing.total = time_since(frame_start);
+            self.timing.parse = self.timing.total.saturating_sub(self.timing.mbs + self.timing.postproc);
+        }
+        self.session.frames += 1;
+        if hdr.is_intra {
+            self.session.keyframes += 1;
+        }
+        self.session.bytes += src.len();
+        self.session.quant_sum += u64::from(hdr.quant);
+        self.session.decode_time += self.timing.total;
+
+        Ok((out, if hdr.is_intra { FrameType::I } else { FrameType::P }))
     }
-    fn decode_planes(&mut self, br: &mut dyn VP56Parser, dframe: &mut NASimpleVideoFrame<u8>, bc: &mut BoolCoder, hdr: &VP56Header, src: &[u8], alpha: bool) -> DecoderResult<()> {
+    // decodes the final packet of a stream that may be truncated: if the macroblock data ends prematurely
+    // the frame is returned with the macroblocks decoded so far and the rest filled as by error concealment;
+    // decode_frame() still reports ShortData in this case and errors in the frame headers are reported here as well
+    pub fn decode_last_frame(&mut self, supp: &mut NADecoderSupport, src: &[u8], br: &mut dyn VP56Parser) -> DecoderResult<(NABufferType, FrameType)> {
+        self.eof = true;
+        let ret = self.decode_frame(supp, src, br);
+        self.eof = false;
+        ret
+    }
+    // malformed input should always be reported as a normal decoding error,
+    // this additionally turns any remaining internal failure (panic) into DecoderError::Bug
+    // and flushes the decoder so it can resume from the next keyframe;
+    // panics can be caught only with panic = "unwind", builds with panic = "abort" (like most fuzzing setups) still abort here
+    pub fn decode_checked(&mut self, supp: &mut NADecoderSupport, src: &[u8], br: &mut dyn VP56Parser) -> DecoderResult<(NABufferType, FrameType)> {
+        match catch_unwind(AssertUnwindSafe(|| self.decode_frame(supp, src, br))) {
+            Ok(ret) => ret,
+            Err(_) => {
+                self.flush();
+                Err(DecoderError::Bug)
+            },
+        }
+    }
+    // the decoder still keeps its own copy of the frame for reference purposes,
+    // the output buffer should match the frames decode_frame() would return (without cropping information)
+    // and it is checked against the frame header before the decoder or parser state is changed
+    pub fn decode_into<P: VP56Parser + Clone>(&mut self, supp: &mut NADecoderSupport, src: &[u8], br: &mut P, out: &mut NAVideoBuffer<u8>) -> DecoderResult<FrameType> {
+        let csrc = if self.has_alpha { src.get(3..).unwrap_or(&[]) } else { src };
+        let (width, height) = match BoolCoder::new(csrc).and_then(|mut bc| br.clone().parse_header(&mut bc)) {
+                Ok(ref hdr) if hdr.mb_w != 0 => (usize::from(hdr.mb_w) * 16, usize::from(hdr.mb_h) * 16),
+                _ => (self.width, self.height),
+            };
+        validate!(out.get_info() == NAVideoInfo::new(width, height, self.flip, self.output_pix_fmt()));
+        let (buf, ftype) = self.decode_frame_int(supp, src, br, Some(out))?;
+        // a substitute for the frame with a missing reference
+        if let Some(sbuf) = buf.get_vbuf() {
+            validate!(sbuf.get_info() == out.get_info());
+            vp_copy_frame(&sbuf, out);
+        }
+        Ok(ftype)
+    }
+    fn output_into(&mut self, supp: &mut NADecoderSupport, buf: &NAVideoBufferRef<u8>, loop_str: i16, dst: &mut NAVideoBuffer<u8>) -> DecoderResult<()> {
+        match self.out_fmt {
+            VP56OutputFormat::YUV => {
+                vp_copy_frame(buf, dst);
+                vp_postprocess(dst, self.postproc, loop_str);
+                self.fingerprint = if self.calc_fp { Some(vp_fingerprint(dst)) } else { None };
+            },
+            VP56OutputFormat::RGB24 => {
+                if self.postproc > 0 {
+                    let mut pbuf = supp.pool_u8.get_copy(buf).ok_or(DecoderError::AllocError)?;
+                    vp_postprocess(&mut pbuf, self.postproc, loop_str);
+                    vp_yuv2rgb(&pbuf, dst);
+                    self.fingerprint = if self.calc_fp { Some(vp_fingerprint(&pbuf)) } else { None };
+                } else {
+                    vp_yuv2rgb(buf, dst);
+                    self.fingerprint = if self.calc_fp { Some(vp_fingerprint(buf)) } else { None };
+                }
+            },
+        }
+        dst.set_crop_rect(buf.get_crop_rect());
+        if self.miss_ref == VP56MissingRef::Last {
+            // the copy is made the same way as the output of decode_frame() would be
+            self.last_out = Some(match self.out_fmt {
+                    VP56OutputFormat::YUV   => {
+                        let mut copy = supp.pool_u8.get_free().ok_or(DecoderError::AllocError)?;
+                        vp_copy_frame(dst, &mut copy);
+                        copy.set_crop_rect(dst.get_crop_rect());
+                        NABufferType::Video(copy)
+                    },
+                    VP56OutputFormat::RGB24 => {
+                        let mut copy = alloc_video_buffer(dst.get_info(), 0)?.get_vbuf().ok_or(DecoderError::Bug)?;
+                        vp_copy_frame(dst, &mut copy);
+                        copy.set_crop_rect(dst.get_crop_rect());
+                        NABufferType::VideoPacked(copy)
+                    },
+                });
+        }
+        Ok(())
+    }
+    // returns the number of concealed macroblocks
+    fn decode_planes(&mut self, br: &mut dyn VP56Parser, dframe: &mut NASimpleVideoFrame<u8>, bc: &mut BoolCoder, hdr: &VP56Header, src: &[u8], alpha: bool) -> DecoderResult<usize> {
         let mut cr;
         if hdr.multistream {
             let off = hdr.offset as usize;
+            validate!(off < src.len());
             if !hdr.use_huffman {
                 let bc2 = BoolCoder::new(&src[off..])?;
                 cr = CoeffReader::Bool(bc2);
@@ -624,6 +1128,12 @@ impl VP56Decoder {
         self.last_dc[0][2] = 0x80;
         self.dc_pred.reset();
 
+        let mb_start = self.timer_start();
+        let ret = self.decode_mbs(dframe, bc, &mut cr, br, hdr, alpha);
+        self.timing.mbs += time_since(mb_start);
+        ret
+    }
+    fn decode_mbs(&mut self, dframe: &mut NASimpleVideoFrame<u8>, bc: &mut BoolCoder, cr: &mut CoeffReader, br: &mut dyn VP56Parser, hdr: &VP56Header, alpha: bool) -> DecoderResult<usize> {
         self.ilace_mb = false;
         for mb_y in 0..self.mb_h {
             self.fstate.mb_y = mb_y;
@@ -631,12 +1141,51 @@ impl VP56Decoder {
             self.fstate.last_idx = [24; 4];
             for mb_x in 0..self.mb_w {
                 self.fstate.mb_x = mb_x;
-                self.decode_mb(dframe, bc, &mut cr, br, hdr, alpha)?;
+                if let Err(err) = self.decode_mb(dframe, bc, cr, br, hdr, alpha) {
+                    let recover = match err {
+                            DecoderError::InvalidData => self.conceal,
+                            DecoderError::ShortData   => self.conceal || self.eof,
+                            _ => false,
+                        };
+                    if !recover {
+                        return Err(err);
+                    }
+                    return Ok(self.conceal_mbs(dframe, mb_x + mb_y * self.mb_w, alpha));
+                }
                 self.dc_pred.next_mb();
             }
             self.dc_pred.update_row();
         }
-        Ok(())
+        Ok(0)
+    }
+    fn conceal_mbs(&mut self, frm: &mut NASimpleVideoFrame<u8>, start: usize, alpha: bool) -> usize {
+        let planes: &[usize] = if alpha { &[3] } else if self.luma_only { &[0] } else { &[0, 1, 2] };
+        let src = self.shuf.get_last();
+        for mb_pos in start..self.mb_w * self.mb_h {
+            self.mb_info[mb_pos] = MBInfo { mb_type: VPMBType::InterNoMV, mv: ZERO_MV };
+            let mb_x = mb_pos % self.mb_w;
+            let mb_y = mb_pos / self.mb_w;
+            for &plane in planes.iter() {
+                let size = if plane == 1 || plane == 2 { 8 } else { 16 };
+                let dstride = frm.stride[plane];
+                let doff = frm.offset[plane] + mb_x * size + mb_y * size * dstride;
+                if let Some(ref sbuf) = src {
+                    let sstride = sbuf.get_stride(plane);
+                    let soff = sbuf.get_offset(plane) + mb_x * size + mb_y * size * sstride;
+                    let sdata = sbuf.get_data();
+                    for (dline, sline) in frm.data[doff..].chunks_mut(dstride).zip(sdata[soff..].chunks(sstride)).take(size) {
+                        dline[..size].copy_from_slice(&sline[..size]);
+                    }
+                } else {
+                    for dline in frm.data[doff..].chunks_mut(dstride).take(size) {
+                        for el in dline[..size].iter_mut() {
+                            *el = 128;
+                        }
+                    }
+                }
+            }
+        }
+        self.mb_w * self.mb_h - start
     }
     fn reset_mbtype_models(&mut self) {
         const DEFAULT_XMITTED_PROBS: [[u8; 20]; 3] = [
@@ -943,7 +1492,7 @@ impl VP56Decoder {
             };
             self.top_ctx[self.fstate.plane][mb_x] = self.fstate.top_ctx;
             self.predict_dc(mb_type, mb_pos, blk_no, alpha);
-            if !alpha {
+            if !alpha && !self.luma_only {
                 let has_ac = self.fstate.last_idx[self.fstate.ctx_idx] > 0;
                 if mb_type.is_intra() {
                     if has_ac {
@@ -976,7 +1525,7 @@ impl VP56Decoder {
         br.mc_block(frm, self.mc_buf.clone(), src.clone(), plane, x + 8, y + 0, mv, self.loop_thr);
         br.mc_block(frm, self.mc_buf.clone(), src.clone(), plane, x + 0, y + 8, mv, self.loop_thr);
         br.mc_block(frm, self.mc_buf.clone(), src.clone(), plane, x + 8, y + 8, mv, self.loop_thr);
-        if !alpha {
+        if !alpha && !self.luma_only {
             let x = self.fstate.mb_x * 8;
             let y = self.fstate.mb_y * 8;
             br.mc_block(frm, self.mc_buf.clone(), src.clone(), 1, x, y, mv, self.loop_thr);
@@ -993,7 +1542,7 @@ impl VP56Decoder {
                         plane, x + (blk_no & 1) * 8, y + (blk_no & 2) * 4,
                         mvs[blk_no], self.loop_thr);
         }
-        if !alpha {
+        if !alpha && !self.luma_only {
             let x = self.fstate.mb_x * 8;
             let y = self.fstate.mb_y * 8;
             let sum = mvs[0] + mvs[1] + mvs[2] + mvs[3];
@@ -1161,3 +1710,28 @@ const VP56_MODE_VQ: [[[u8; 20]; 16]; 3] = [
   ]
 ];
 
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_motion() {
+        let mut dec = VP56Decoder::new(6, false, false);
+        dec.set_dimensions(48, 32);
+        assert_eq!(dec.get_motion_vectors(), None);
+        dec.set_motion_export(true);
+        assert_eq!(dec.get_motion_vectors(), None);
+        dec.mb_info[1] = MBInfo { mb_type: VPMBType::InterMV, mv: MV::new(5, -3) };
+        dec.mb_info[4] = MBInfo { mb_type: VPMBType::GoldenNear, mv: MV::new(-8, 12) };
+        dec.mb_info[5] = MBInfo { mb_type: VPMBType::InterNoMV, mv: ZERO_MV };
+        dec.export_motion();
+        let (w, h, mvs) = dec.get_motion_vectors().unwrap();
+        assert_eq!((w, h, mvs.len()), (3, 2, 6));
+        assert_eq!(mvs[0], VP56MBMotion { mv: ZERO_MV, ref_id: 0 });
+        assert_eq!(mvs[1], VP56MBMotion { mv: MV::new(5, -3), ref_id: VP_REF_INTER });
+        assert_eq!(mvs[4], VP56MBMotion { mv: MV::new(-8, 12), ref_id: VP_REF_GOLDEN });
+        assert_eq!(mvs[5], VP56MBMotion { mv: ZERO_MV, ref_id: VP_REF_INTER });
+        dec.set_motion_export(false);
+        assert_eq!(dec.get_motion_vectors(), None);
+    }
+}
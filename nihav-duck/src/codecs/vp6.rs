@@ -6,7 +6,34 @@ use super::vpcommon::*;
 pub use super::vp56::*;
 use super::vp6data::*;
 
-#[derive(Default)]
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub enum VP6ReservedBits {
+    // reject streams with reserved profile values
+    #[default]
+    Reject,
+    // decode them as simple or advanced profile depending on the upper profile bit
+    Ignore,
+}
+
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub enum VP6MCFilter {
+    // use the interpolation filter selected by the stream
+    #[default]
+    Auto,
+    // always use bilinear interpolation (faster but the output drifts from the reference decoder)
+    Bilinear,
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct VP6StreamInfo {
+    // bitstream version (VERSION_VP60 to VERSION_VP62)
+    pub version:    u8,
+    // advanced profile streams may use loop filtering and bicubic interpolation
+    pub advanced:   bool,
+    pub interlaced: bool,
+}
+
+#[derive(Clone,Default)]
 pub struct VP6BR {
     vpversion:      u8,
     profile:        u8,
@@ -18,12 +45,114 @@ pub struct VP6BR {
     mv_thresh:      u8,
     bicubic:        bool,
     filter_alpha:   usize,
+    reserved_bits:  VP6ReservedBits,
+    mc_filter:      VP6MCFilter,
 }
 
 impl VP6BR {
     pub fn new() -> Self {
         Self::default()
     }
+    pub fn set_reserved_bits_mode(&mut self, mode: VP6ReservedBits) {
+        self.reserved_bits = mode;
+    }
+    pub fn set_mc_filter(&mut self, filter: VP6MCFilter) {
+        self.mc_filter = filter;
+    }
+    // reports profile and bitstream version, they are known only after the first keyframe has been parsed
+    pub fn get_profile(&self) -> Option<(u8, u8)> {
+        if self.vpversion != 0 {
+            Some((self.profile, self.vpversion))
+        } else {
+            None
+        }
+    }
+    pub fn get_stream_info(&self) -> Option<VP6StreamInfo> {
+        if self.vpversion != 0 {
+            Some(VP6StreamInfo {
+                    version:    self.vpversion,
+                    advanced:   self.profile == VP6_ADVANCED_PROFILE,
+                    interlaced: self.interlaced,
+                })
+        } else {
+            None
+        }
+    }
+}
+
+// Reports the output format the decoder will most likely produce for the stream,
+// judging by the codec name and the FLV-style cropping information in the extradata.
+// It is a best-effort guess since streams may change the picture size on any keyframe.
+pub fn vp6_probe_output_format(info: &NACodecInfoRef) -> DecoderResult<NAVideoInfo> {
+    if let NACodecTypeInfo::Video(vinfo) = info.get_properties() {
+        let (flip, has_alpha) = match info.get_name() {
+                "vp6"  => (true,  false),
+                "vp6f" => (false, false),
+                "vp6a" => (false, true),
+                _ => return Err(DecoderError::InvalidData),
+            };
+        let fmt = if !has_alpha { YUV420_FORMAT } else { VP_YUVA420_FORMAT };
+        let mut width  = vinfo.get_width();
+        let mut height = vinfo.get_height();
+        if let Some(edata) = info.get_extradata() {
+            if !edata.is_empty() && (width & 0xF) == 0 && (height & 0xF) == 0 {
+                width  = width.checked_sub(usize::from(edata[0] >> 4)).ok_or(DecoderError::InvalidData)?;
+                height = height.checked_sub(usize::from(edata[0] & 0xF)).ok_or(DecoderError::InvalidData)?;
+            }
+        }
+        Ok(NAVideoInfo::new(width, height, flip, fmt))
+    } else {
+        Err(DecoderError::InvalidData)
+    }
+}
+
+// Checks whether the packet contains a keyframe by looking at the frame header only.
+pub fn vp6_is_keyframe(src: &[u8], has_alpha: bool) -> DecoderResult<bool> {
+    let hdr = if has_alpha { src.get(3..) } else { Some(src) }.unwrap_or(&[]);
+    validate!(hdr.len() >= 2);
+    if (hdr[0] & 0x80) != 0 {
+        return Ok(false);
+    }
+    let version = hdr[1] >> 3;
+    validate!((version >= VERSION_VP60) && (version <= VERSION_VP62));
+    Ok(true)
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct VP6KeyframeEntry {
+    // packet number in the stream
+    pub pkt_no:     usize,
+    pub ts:         u64,
+}
+
+// Keyframe index accumulated from stream packets (passed either during decoding or in a separate scanning pass).
+// It is complete only after all stream packets have been added.
+#[derive(Default)]
+pub struct VP6KeyframeIndex {
+    entries:    Vec<VP6KeyframeEntry>,
+    npkts:      usize,
+    has_alpha:  bool,
+}
+
+impl VP6KeyframeIndex {
+    pub fn new(has_alpha: bool) -> Self {
+        Self { has_alpha, ..Default::default() }
+    }
+    // packets should be added in stream order, the return value tells whether it was a keyframe
+    pub fn add_packet(&mut self, ts: u64, src: &[u8]) -> DecoderResult<bool> {
+        let pkt_no = self.npkts;
+        self.npkts += 1;
+        let is_key = vp6_is_keyframe(src, self.has_alpha)?;
+        if is_key {
+            self.entries.push(VP6KeyframeEntry { pkt_no, ts });
+        }
+        Ok(is_key)
+    }
+    pub fn get_entries(&self) -> &[VP6KeyframeEntry] { &self.entries }
+    // returns the last keyframe with timestamp not exceeding the requested one
+    pub fn find_keyframe(&self, ts: u64) -> Option<VP6KeyframeEntry> {
+        self.entries.iter().rev().find(|entry| entry.ts <= ts).copied()
+    }
 }
 
 impl VP56Parser for VP6BR {
@@ -41,9 +170,20 @@ impl VP56Parser for VP6BR {
             hdr.version                         = br.read(5)? as u8;
             validate!((hdr.version >= VERSION_VP60) && (hdr.version <= VERSION_VP62));
             hdr.profile                         = br.read(2)? as u8;
-            validate!((hdr.profile == VP6_SIMPLE_PROFILE) || (hdr.profile == VP6_ADVANCED_PROFILE));
+            if (hdr.profile != VP6_SIMPLE_PROFILE) && (hdr.profile != VP6_ADVANCED_PROFILE) {
+                match self.reserved_bits {
+                    VP6ReservedBits::Reject => return Err(DecoderError::UnsupportedProfile),
+                    VP6ReservedBits::Ignore => {
+                        hdr.profile = if (hdr.profile & 2) != 0 { VP6_ADVANCED_PROFILE } else { VP6_SIMPLE_PROFILE };
+                    },
+                };
+            }
             hdr.interlaced                      = br.read_bool()?;
         } else {
+            // version and profile are known only after a keyframe
+            if self.vpversion == 0 {
+                return Err(DecoderError::MissingReference);
+            }
             hdr.version = self.vpversion;
             hdr.profile = self.profile;
             hdr.interlaced = self.interlaced;
@@ -322,8 +462,9 @@ impl VP56Parser for VP6BR {
             vp31_loop_filter(tmp_blk, off, 16, 1, 12, loop_str);
         }
         let copy_mode = (mx == 0) && (my == 0);
-        let mut bicubic = !copy_mode && is_luma && self.bicubic;
-        if is_luma && !copy_mode && (self.profile == VP6_ADVANCED_PROFILE) {
+        let auto_filter = self.mc_filter == VP6MCFilter::Auto;
+        let mut bicubic = !copy_mode && is_luma && auto_filter && self.bicubic;
+        if is_luma && !copy_mode && auto_filter && (self.profile == VP6_ADVANCED_PROFILE) {
             if !self.autosel_pm {
                 bicubic = true;
             } else {
@@ -424,14 +565,14 @@ fn decode_token_huff(br: &mut BitReader, huff: &VP6Huff) -> DecoderResult<(i16,
     let tok                                     = br.read_huff(huff)?;
     match tok {
         0   => Ok((0, false)),
-        1 | 2 | 3 | 4 => {
+        1..=4 => {
             if !br.read_bool()? {
                 Ok((i16::from(tok), false))
             } else {
                 Ok((-i16::from(tok), false))
             }
         },
-        5 | 6 | 7 | 8 | 9 | 10 => {
+        5..=10 => {
             let base = (tok - 5) as usize;
             let add_bits                        = br.read(VP6_COEF_ADD_BITS[base])? as i16;
             let val = VP56_COEF_BASE[base] + add_bits;
@@ -519,7 +660,7 @@ macro_rules! mc_filter {
         ((i32::from($src[$off - $step]    ) * i32::from($coeffs[0]) +
           i32::from($src[$off]            ) * i32::from($coeffs[1]) +
           i32::from($src[$off + $step]    ) * i32::from($coeffs[2]) +
-          i32::from($src[$off + $step * 2]) * i32::from($coeffs[3]) + 64) >> 7).min(255).max(0) as u8
+          i32::from($src[$off + $step * 2]) * i32::from($coeffs[3]) + 64) >> 7).clamp(0, 255) as u8
     }
 }
 
@@ -590,3 +731,1138 @@ fn mc_bicubic(dst: &mut [u8], dstride: usize, src: &[u8], mut soff: usize, sstri
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    struct BoolEncoder {
+        dst:    Vec<u8>,
+        low:    u32,
+        range:  u32,
+        count:  i32,
+    }
+
+    impl BoolEncoder {
+        fn new(dst: Vec<u8>) -> Self {
+            Self { dst, low: 0, range: 255, count: -24 }
+        }
+        fn put_prob(&mut self, bit: bool, prob: u8) {
+            let split = 1 + (((self.range - 1) * u32::from(prob)) >> 8);
+            if bit {
+                self.low   += split;
+                self.range -= split;
+            } else {
+                self.range  = split;
+            }
+            let mut shift = self.range.leading_zeros() as i32 - 24;
+            self.range <<= shift;
+            self.count += shift;
+            if self.count >= 0 {
+                let offset = shift - self.count;
+                if ((self.low << (offset - 1)) & 0x80000000) != 0 {
+                    for el in self.dst.iter_mut().rev() {
+                        if *el == 0xFF {
+                            *el = 0;
+                        } else {
+                            *el += 1;
+                            break;
+                        }
+                    }
+                }
+                self.dst.push((self.low >> (24 - offset)) as u8);
+                self.low <<= offset;
+                shift = self.count;
+                self.low &= 0xFFFFFF;
+                self.count -= 8;
+            }
+            self.low <<= shift;
+        }
+        fn put_bits(&mut self, val: u32, bits: u8) {
+            for i in (0..bits).rev() {
+                self.put_prob(((val >> i) & 1) != 0, 128);
+            }
+        }
+        // after flushing the coder produces zero bits for any probability
+        fn finish(mut self) -> Vec<u8> {
+            for _ in 0..64 {
+                self.put_prob(false, 128);
+            }
+            self.dst
+        }
+    }
+
+    #[derive(Clone,Copy)]
+    struct TestFrame<'a> {
+        intra:      bool,
+        golden:     bool,
+        quant:      u8,
+        version:    u8,
+        profile:    u8,
+        interlaced: bool,
+        mb_w:       u8,
+        mb_h:       u8,
+        disp_w:     u8,
+        disp_h:     u8,
+        // coefficients are coded with Huffman codes in a separate partition
+        huff_data:  Option<&'a [u8]>,
+        // all macroblocks are coded as field macroblocks
+        // (used only for interlaced intra frames with Huffman-coded coefficients)
+        field_mbs:  bool,
+    }
+
+    impl Default for TestFrame<'_> {
+        fn default() -> Self {
+            Self {
+                intra:      true,
+                golden:     false,
+                quant:      32,
+                version:    VERSION_VP62,
+                profile:    VP6_ADVANCED_PROFILE,
+                interlaced: false,
+                mb_w:       4,
+                mb_h:       3,
+                disp_w:     4,
+                disp_h:     3,
+                huff_data:  None,
+                field_mbs:  false,
+            }
+        }
+    }
+
+    // generates a frame that has all macroblocks coded as intra macroblocks
+    // without any coefficients (or as inter macroblocks without motion vector and residue)
+    fn gen_frame(frm: &TestFrame) -> Vec<u8> {
+        let mut hdr = Vec::new();
+        hdr.push(((!frm.intra as u8) << 7) | (frm.quant << 1) | (frm.huff_data.is_some() as u8));
+        if frm.intra {
+            hdr.push((frm.version << 3) | (frm.profile << 1) | (frm.interlaced as u8));
+        }
+        assert!((frm.profile & 2) != 0);
+        let offset_pos = hdr.len();
+        if frm.huff_data.is_some() {
+            hdr.extend_from_slice(&[0, 0]);
+        }
+
+        let mut bc = BoolEncoder::new(hdr);
+        if frm.intra {
+            bc.put_bits(u32::from(frm.mb_h), 8);
+            bc.put_bits(u32::from(frm.mb_w), 8);
+            bc.put_bits(u32::from(frm.disp_h), 8);
+            bc.put_bits(u32::from(frm.disp_w), 8);
+            bc.put_bits(0, 2);
+        } else {
+            bc.put_bits(frm.golden as u32, 1);
+        }
+        if let Some(huff_data) = frm.huff_data {
+            // no loop filter or MC mode parameters, then the Huffman coding flag
+            bc.put_bits(0, if frm.intra { 6 } else { 2 });
+            bc.put_bits(1, 1);
+            if frm.intra && frm.interlaced {
+                put_ilace_flags(&mut bc, frm);
+            }
+            let mut frame = bc.finish();
+            let offset = frame.len();
+            frame[offset_pos]     = (offset >> 8) as u8;
+            frame[offset_pos + 1] = offset as u8;
+            frame.extend_from_slice(huff_data);
+            frame
+        } else {
+            bc.finish()
+        }
+    }
+
+    // codes coefficient models without updates, interlacing probability
+    // and the field coding flags for all macroblocks
+    fn put_ilace_flags(bc: &mut BoolEncoder, frm: &TestFrame) {
+        for probs in HAS_COEF_PROBS.iter() {
+            for &prob in probs.iter() {
+                bc.put_prob(false, prob);
+            }
+        }
+        bc.put_prob(false, 128);
+        for probs in HAS_ZERO_RUN_PROBS.iter() {
+            for &prob in probs.iter() {
+                bc.put_prob(false, prob);
+            }
+        }
+        for ctype in VP6_AC_PROBS.iter() {
+            for plane in ctype.iter() {
+                for group in plane.iter() {
+                    for &prob in group.iter() {
+                        bc.put_prob(false, prob);
+                    }
+                }
+            }
+        }
+        const ILACE_PROB: u8 = 128;
+        bc.put_bits(u32::from(ILACE_PROB), 8);
+        for _ in 0..frm.mb_h {
+            for mb_x in 0..frm.mb_w {
+                let prob = if mb_x == 0 {
+                        ILACE_PROB
+                    } else if !frm.field_mbs {
+                        ILACE_PROB + (((256 - u16::from(ILACE_PROB)) >> 1) as u8)
+                    } else {
+                        ILACE_PROB - (ILACE_PROB >> 1)
+                    };
+                bc.put_prob(frm.field_mbs, prob);
+            }
+        }
+    }
+
+    // writes Huffman-coded blocks using the code tables for the default intra frame models
+    struct HuffWriter {
+        bits:       Vec<bool>,
+        dc_tree:    [VP6Huff; 2],
+        ac_tree:    [[[VP6Huff; 4]; 3]; 2],
+        zrun_tree:  VP6Huff,
+    }
+
+    impl HuffWriter {
+        fn new() -> Self {
+            let mut dc_tree = [VP6Huff::default(); 2];
+            let mut ac_tree = [[[VP6Huff::default(); 4]; 3]; 2];
+            let mut zrun_tree = VP6Huff::default();
+            for (dc_tree, ac_tree) in dc_tree.iter_mut().zip(ac_tree.iter_mut()) {
+                dc_tree.build_codes(&[128; 11]);
+                for tree in ac_tree.iter_mut().flatten() {
+                    tree.build_codes(&[128; 11]);
+                }
+            }
+            zrun_tree.build_codes_zero_run(&ZERO_RUN_PROBS[0]);
+            Self { bits: Vec::new(), dc_tree, ac_tree, zrun_tree }
+        }
+        fn put_bits(&mut self, val: u16, bits: u8) {
+            for i in (0..bits).rev() {
+                self.bits.push(((val >> i) & 1) != 0);
+            }
+        }
+        fn put_sym(&mut self, tree: &VP6Huff, sym: usize) {
+            self.put_bits(tree.codes[sym], tree.bits[sym]);
+        }
+        fn put_token(&mut self, tree: &VP6Huff, val: i16) {
+            assert!(val.abs() <= 4);
+            self.put_sym(tree, val.unsigned_abs() as usize);
+            if val != 0 {
+                self.put_bits((val < 0) as u16, 1);
+            }
+        }
+        // codes a block with the provided DC value and an optional coefficient
+        // for the first vertical frequency
+        fn put_block(&mut self, plane: usize, dc: i16, ac: i16) {
+            const EOB: usize = 11;
+            let dc_tree = self.dc_tree[plane];
+            self.put_token(&dc_tree, dc);
+            if dc == 0 {
+                self.put_bits(0, 2);
+            }
+            let ac_tree = self.ac_tree[plane];
+            let mode = dc.unsigned_abs().min(2) as usize;
+            if ac == 0 {
+                self.put_sym(&ac_tree[mode][VP6_IDX_TO_AC_BAND[1]], EOB);
+                self.put_bits(0, 2);
+            } else {
+                let zrun_tree = self.zrun_tree;
+                self.put_token(&ac_tree[mode][VP6_IDX_TO_AC_BAND[1]], 0);
+                self.put_sym(&zrun_tree, 0);
+                self.put_token(&ac_tree[0][VP6_IDX_TO_AC_BAND[2]], ac);
+                let mode = ac.unsigned_abs().min(2) as usize;
+                self.put_sym(&ac_tree[mode][VP6_IDX_TO_AC_BAND[3].min(3)], EOB);
+            }
+        }
+        fn finish(self) -> Vec<u8> {
+            let mut dst = vec![0; self.bits.len().div_ceil(8) + 4];
+            for (i, &bit) in self.bits.iter().enumerate() {
+                dst[i >> 3] |= (bit as u8) << (7 - (i & 7));
+            }
+            dst
+        }
+    }
+
+    fn gen_packet(frm: &TestFrame, alpha: Option<&TestFrame>) -> Vec<u8> {
+        let color = gen_frame(frm);
+        if let Some(afrm) = alpha {
+            let mut pkt = vec![(color.len() >> 16) as u8, (color.len() >> 8) as u8, color.len() as u8];
+            pkt.extend_from_slice(&color);
+            pkt.extend_from_slice(&gen_frame(afrm));
+            pkt
+        } else {
+            color
+        }
+    }
+
+    fn init_decoder(width: usize, height: usize, alpha: bool) -> (VP56Decoder, VP6BR, NADecoderSupport) {
+        let mut dec = VP56Decoder::new(6, alpha, false);
+        let mut supp = NADecoderSupport::new();
+        let fmt = if alpha { VP_YUVA420_FORMAT } else { YUV420_FORMAT };
+        dec.init(&mut supp, NAVideoInfo::new(width, height, false, fmt)).unwrap();
+        (dec, VP6BR::new(), supp)
+    }
+
+    #[test]
+    fn test_vp6_crop() {
+        let frm = TestFrame { mb_w: 11, mb_h: 9, disp_w: 10, disp_h: 8, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(176, 144, false);
+        let (buf, ftype) = dec.decode_frame(&mut supp, &gen_packet(&frm, None), &mut br).unwrap();
+        assert_eq!(ftype, FrameType::I);
+        let vbuf = buf.get_vbuf().unwrap();
+        let vinfo = vbuf.get_info();
+        assert_eq!((vinfo.get_width(), vinfo.get_height()), (176, 144));
+        assert_eq!(vbuf.get_crop_rect(), Some((0, 0, 160, 128)));
+        assert_eq!(vbuf.get_stride(0), 176);
+        assert_eq!(vbuf.get_data()[vbuf.get_offset(0)], 128);
+
+        let frm = TestFrame { mb_w: 11, mb_h: 9, disp_w: 11, disp_h: 9, ..Default::default() };
+        let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(&frm, None), &mut br).unwrap();
+        assert_eq!(buf.get_vbuf().unwrap().get_crop_rect(), None);
+    }
+
+    #[test]
+    fn test_vp6_reserved_profile() {
+        let frm = TestFrame { profile: 2, ..Default::default() };
+        let pkt = gen_packet(&frm, None);
+
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        assert_eq!(dec.decode_frame(&mut supp, &pkt, &mut br).err(), Some(DecoderError::UnsupportedProfile));
+
+        br.set_reserved_bits_mode(VP6ReservedBits::Ignore);
+        let (_, ftype) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+        assert_eq!(ftype, FrameType::I);
+    }
+
+    #[test]
+    fn test_vp6_alpha_keyframe_mismatch() {
+        let intra = TestFrame::default();
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, true);
+
+        assert_eq!(dec.decode_frame(&mut supp, &gen_packet(&intra, Some(&inter)), &mut br).err(), Some(DecoderError::MissingReference));
+
+        for &(color, alpha) in [(&intra, &intra), (&inter, &intra), (&intra, &inter), (&inter, &inter)].iter() {
+            let (buf, ftype) = dec.decode_frame(&mut supp, &gen_packet(color, Some(alpha)), &mut br).unwrap();
+            assert_eq!(ftype, if color.intra { FrameType::I } else { FrameType::P });
+            let vbuf = buf.get_vbuf().unwrap();
+            assert!(vbuf.get_info().get_format() == VP_YUVA420_FORMAT);
+            assert_eq!(vbuf.get_dimensions(3), (64, 48));
+            let aoff = vbuf.get_offset(3);
+            let astride = vbuf.get_stride(3);
+            for line in vbuf.get_data()[aoff..].chunks(astride).take(48) {
+                assert!(line[..64].iter().all(|&a| a == 128));
+            }
+        }
+    }
+
+    #[test]
+    fn test_vp6_interlaced() {
+        let mut hw = HuffWriter::new();
+        for _ in 0..12 {
+            for _ in 0..4 {
+                hw.put_block(0, 0, 4);
+            }
+            hw.put_block(1, 0, 0);
+            hw.put_block(1, 0, 0);
+        }
+        let huff_data = hw.finish();
+        let progr = TestFrame { interlaced: true, huff_data: Some(&huff_data), ..Default::default() };
+        let field = TestFrame { field_mbs: true, ..progr };
+        let inter = TestFrame { intra: false, ..Default::default() };
+
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        let pbuf = dec.decode_frame(&mut supp, &gen_packet(&progr, None), &mut br).unwrap().0.get_vbuf().unwrap();
+        assert!(br.interlaced);
+        let fbuf = dec.decode_frame(&mut supp, &gen_packet(&field, None), &mut br).unwrap().0.get_vbuf().unwrap();
+
+        let stride = pbuf.get_stride(0);
+        let pdata = &pbuf.get_data()[pbuf.get_offset(0)..];
+        let fdata = &fbuf.get_data()[fbuf.get_offset(0)..];
+        let pline = |y: usize| &pdata[y * stride..][..64];
+        let fline = |y: usize| &fdata[y * stride..][..64];
+        assert_ne!(pline(0), pline(1));
+        // field macroblocks have the lines of the top block in even lines and the lines of the bottom block in odd lines
+        for mb_y in 0..3 {
+            for y in 0..8 {
+                assert_eq!(fline(mb_y * 16 + y * 2),     pline(mb_y * 16 + y));
+                assert_eq!(fline(mb_y * 16 + y * 2 + 1), pline(mb_y * 16 + y + 8));
+            }
+        }
+        for plane in 1..3 {
+            let (soff, doff) = (pbuf.get_offset(plane), fbuf.get_offset(plane));
+            let cstride = pbuf.get_stride(plane);
+            for (pline, fline) in pbuf.get_data()[soff..].chunks(cstride).zip(fbuf.get_data()[doff..].chunks(cstride)).take(24) {
+                assert_eq!(pline[..32], fline[..32]);
+            }
+        }
+
+        // inter frames without residue keep the field-coded picture
+        let field_luma: Vec<u8> = (0..48).flat_map(|y| fline(y).to_vec()).collect();
+        std::mem::drop(pbuf);
+        std::mem::drop(fbuf);
+        for _ in 0..2 {
+            let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(&inter, None), &mut br).unwrap();
+            let vbuf = buf.get_vbuf().unwrap();
+            let data = &vbuf.get_data()[vbuf.get_offset(0)..];
+            for (y, line) in field_luma.chunks(64).enumerate() {
+                assert_eq!(&data[y * stride..][..64], line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vp6_postproc() {
+        let intra = TestFrame { quant: 2, ..Default::default() };
+        let inter = TestFrame { intra: false, quant: 2, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        dec.set_postproc_level(VP_MAX_POSTPROC_LEVEL);
+        let pool_size = supp.pool_u8.buffer_count().0;
+        let mut prev: Option<NAVideoBufferRef<u8>> = None;
+        for frm in [intra, inter, inter, inter, inter].iter() {
+            let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(frm, None), &mut br).unwrap();
+            let vbuf = buf.get_vbuf().unwrap();
+            let off = vbuf.get_offset(0);
+            let stride = vbuf.get_stride(0);
+            for line in vbuf.get_data()[off..].chunks(stride).take(48) {
+                assert!(line[..64].iter().all(|&pix| pix == 128));
+            }
+            if let Some(ref pbuf) = prev {
+                assert!(!std::ptr::eq(vbuf.get_data().as_ptr(), pbuf.get_data().as_ptr()));
+            }
+            prev = Some(vbuf);
+        }
+        // one frame is reserved for the postprocessed output, the output held by the caller does not make the pool grow further
+        assert_eq!(supp.pool_u8.buffer_count().0, pool_size + 1);
+
+        // blocks with alternating DC values produce visible block edges
+        let mut hw = HuffWriter::new();
+        for _ in 0..12 {
+            for blk_no in 0..4 {
+                hw.put_block(0, if (blk_no & 1) == 0 { 1 } else { -1 }, 0);
+            }
+            hw.put_block(1, 0, 0);
+            hw.put_block(1, 0, 0);
+        }
+        let huff_data = hw.finish();
+        let blocky = TestFrame { quant: 2, huff_data: Some(&huff_data), ..Default::default() };
+        let pkt = gen_packet(&blocky, None);
+        let edge_diff = |vbuf: &NAVideoBufferRef<u8>| -> u32 {
+            let stride = vbuf.get_stride(0);
+            let data = &vbuf.get_data()[vbuf.get_offset(0)..];
+            let diff = |a: usize, b: usize| u32::from(data[a].max(data[b]) - data[a].min(data[b]));
+            let mut sum = 0;
+            for y in 0..48 {
+                for x in (8..64).step_by(8) {
+                    sum += diff(x + y * stride, x - 1 + y * stride);
+                }
+            }
+            for y in (8..48).step_by(8) {
+                for x in 0..64 {
+                    sum += diff(x + y * stride, x + (y - 1) * stride);
+                }
+            }
+            sum
+        };
+
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        let (buf, _) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+        let ref_buf = buf.get_vbuf().unwrap();
+        let mut prev_diff = edge_diff(&ref_buf);
+        assert!(prev_diff > 0);
+        for level in 0..=VP_MAX_POSTPROC_LEVEL {
+            let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+            dec.set_postproc_level(level);
+            let (buf, _) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+            let vbuf = buf.get_vbuf().unwrap();
+            let cur_diff = edge_diff(&vbuf);
+            if level == 0 {
+                assert_eq!(vbuf.get_data(), ref_buf.get_data());
+            } else {
+                assert!(cur_diff <= prev_diff);
+            }
+            prev_diff = cur_diff;
+        }
+        assert!(prev_diff < edge_diff(&ref_buf));
+    }
+
+    #[test]
+    fn test_vp6_mc_filter() {
+        let vinfo = NAVideoInfo::new(64, 64, false, YUV420_FORMAT);
+        let mut src = alloc_video_buffer(vinfo, 4).unwrap().get_vbuf().unwrap();
+        let stride = src.get_stride(0);
+        for (i, el) in src.get_data_mut().unwrap().iter_mut().enumerate() {
+            let (x, y) = (i % stride, i / stride);
+            *el = ((x * 37 + y * 91 + x * y) & 0xFF) as u8;
+        }
+        let mc_buf = alloc_video_buffer(NAVideoInfo::new(24, 24, false, VP_YUVA420_FORMAT), 4).unwrap().get_vbuf().unwrap();
+
+        let mc = |br: &VP6BR| -> Vec<u8> {
+            let mut dbuf = alloc_video_buffer(vinfo, 4).unwrap().get_vbuf().unwrap();
+            let mut dst = NASimpleVideoFrame::from_video_buf(&mut dbuf).unwrap();
+            br.mc_block(&mut dst, mc_buf.clone(), src.clone(), 0, 16, 16, MV{ x: 3, y: -5 }, 0);
+            dbuf.get_data().to_vec()
+        };
+
+        let mut br = VP6BR::new();
+        br.profile = VP6_ADVANCED_PROFILE;
+        br.filter_alpha = 16;
+        let auto_bicubic = mc(&br);
+        br.set_mc_filter(VP6MCFilter::Bilinear);
+        let forced_bilinear = mc(&br);
+        assert_ne!(auto_bicubic, forced_bilinear);
+
+        let mut br = VP6BR::new();
+        br.profile = VP6_SIMPLE_PROFILE;
+        let auto_bilinear = mc(&br);
+        assert_eq!(auto_bilinear, forced_bilinear);
+    }
+
+    #[test]
+    fn test_vp6_postproc_last_out() {
+        let intra = TestFrame { quant: 2, ..Default::default() };
+        let inter = TestFrame { intra: false, quant: 2, ..Default::default() };
+        for &(into, level) in [(false, VP_MAX_POSTPROC_LEVEL), (true, 0), (true, VP_MAX_POSTPROC_LEVEL)].iter() {
+            let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+            dec.set_postproc_level(level);
+            dec.set_missing_ref_mode(VP56MissingRef::Last);
+            let mut out = alloc_video_buffer(NAVideoInfo::new(64, 48, false, YUV420_FORMAT), 4).unwrap().get_vbuf().unwrap();
+            let mut pool_size = 0;
+            for (i, frm) in [intra, inter, inter, intra, inter, inter].iter().enumerate() {
+                let pkt = gen_packet(frm, None);
+                if into {
+                    dec.decode_into(&mut supp, &pkt, &mut br, &mut out).unwrap();
+                } else {
+                    dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+                }
+                // the postprocessed frame and the kept last output copy come from the pool reserve
+                if i == 0 {
+                    pool_size = supp.pool_u8.buffer_count().0;
+                }
+                assert_eq!(supp.pool_u8.buffer_count().0, pool_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_vp6_missing_ref() {
+        let intra = TestFrame::default();
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let inter_pkt = gen_packet(&inter, None);
+        // keyframe with its coefficient partition pointing to the very end of the packet
+        let mut broken_pkt = gen_frame(&intra);
+        broken_pkt[0] |= 1;
+        let off = broken_pkt.len();
+        broken_pkt.insert(2, (off >> 8) as u8);
+        broken_pkt.insert(3, off as u8);
+
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        assert_eq!(dec.decode_frame(&mut supp, &inter_pkt, &mut br).err(), Some(DecoderError::MissingReference));
+        dec.set_missing_ref_mode(VP56MissingRef::Last);
+        assert_eq!(dec.decode_frame(&mut supp, &inter_pkt, &mut br).err(), Some(DecoderError::MissingReference));
+
+        for &mode in [VP56MissingRef::Error, VP56MissingRef::Last].iter() {
+            dec.set_missing_ref_mode(mode);
+            let (good, _) = dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+            let good = good.get_vbuf().unwrap();
+            assert!(dec.decode_frame(&mut supp, &broken_pkt, &mut br).is_err());
+            let ret = dec.decode_frame(&mut supp, &inter_pkt, &mut br);
+            if mode == VP56MissingRef::Error {
+                assert_eq!(ret.err(), Some(DecoderError::MissingReference));
+            } else {
+                let (buf, ftype) = ret.unwrap();
+                assert_eq!(ftype, FrameType::Skip);
+                assert!(std::ptr::eq(buf.get_vbuf().unwrap().get_data().as_ptr(), good.get_data().as_ptr()));
+            }
+            let (_, ftype) = dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+            assert_eq!(ftype, FrameType::I);
+            let (_, ftype) = dec.decode_frame(&mut supp, &inter_pkt, &mut br).unwrap();
+            assert_eq!(ftype, FrameType::P);
+        }
+    }
+
+    #[test]
+    fn test_vp6_missing_ref_gray() {
+        let intra = TestFrame { mb_w: 4, mb_h: 3, disp_w: 3, disp_h: 3, ..Default::default() };
+        let inter = TestFrame { intra: false, ..Default::default() };
+        for &alpha in [false, true].iter() {
+            let (mut dec, mut br, mut supp) = init_decoder(64, 48, alpha);
+            dec.set_missing_ref_mode(VP56MissingRef::Gray);
+            let inter_pkt = gen_packet(&inter, if alpha { Some(&inter) } else { None });
+            let (buf, ftype) = dec.decode_frame(&mut supp, &inter_pkt, &mut br).unwrap();
+            assert_eq!(ftype, FrameType::Skip);
+            let buf = buf.get_vbuf().unwrap();
+            assert_eq!(buf.get_dimensions(0), (64, 48));
+            assert_eq!(buf.get_crop_rect(), None);
+            assert_eq!(buf.get_num_components(), if alpha { 4 } else { 3 });
+            for (plane, (_, data, _)) in buf.planes().enumerate() {
+                let fill = if plane == 3 { 255 } else { 128 };
+                assert!(data.iter().all(|&el| el == fill));
+            }
+
+            // the gray frame follows the geometry of the last keyframe
+            let intra_pkt = gen_packet(&intra, if alpha { Some(&intra) } else { None });
+            dec.decode_frame(&mut supp, &intra_pkt, &mut br).unwrap();
+            dec.flush();
+            let (buf, ftype) = dec.decode_frame(&mut supp, &inter_pkt, &mut br).unwrap();
+            assert_eq!(ftype, FrameType::Skip);
+            assert_eq!(buf.get_vbuf().unwrap().get_crop_rect(), Some((0, 0, 48, 48)));
+            let (_, ftype) = dec.decode_frame(&mut supp, &intra_pkt, &mut br).unwrap();
+            assert_eq!(ftype, FrameType::I);
+            let (_, ftype) = dec.decode_frame(&mut supp, &inter_pkt, &mut br).unwrap();
+            assert_eq!(ftype, FrameType::P);
+        }
+    }
+
+    #[test]
+    fn test_vp6_frame_stats() {
+        let intra = TestFrame { quant: 17, ..Default::default() };
+        let inter = TestFrame { intra: false, quant: 21, ..Default::default() };
+        let golden = TestFrame { intra: false, golden: true, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+
+        dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+        let stats = dec.get_frame_stats();
+        assert_eq!(stats, VP56FrameStats { quant: 17, intra_mbs: 4 * 3, inter_mbs: 0, golden_update: true, concealed_mbs: 0 });
+
+        dec.decode_frame(&mut supp, &gen_packet(&inter, None), &mut br).unwrap();
+        let stats = dec.get_frame_stats();
+        assert_eq!(stats, VP56FrameStats { quant: 21, intra_mbs: 0, inter_mbs: 4 * 3, golden_update: false, concealed_mbs: 0 });
+
+        dec.decode_frame(&mut supp, &gen_packet(&golden, None), &mut br).unwrap();
+        assert!(dec.get_frame_stats().golden_update);
+    }
+
+    #[test]
+    fn test_vp6_size_change() {
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        for &(mb_w, mb_h) in [(4, 3), (7, 5), (2, 2), (7, 5)].iter() {
+            let intra = TestFrame { mb_w, mb_h, disp_w: mb_w, disp_h: mb_h, ..Default::default() };
+            let inter = TestFrame { intra: false, ..Default::default() };
+            for (frm, ftype) in [(intra, FrameType::I), (inter, FrameType::P)].iter() {
+                let (buf, ft) = dec.decode_frame(&mut supp, &gen_packet(frm, None), &mut br).unwrap();
+                assert_eq!(ft, *ftype);
+                let vbuf = buf.get_vbuf().unwrap();
+                let width  = usize::from(mb_w) * 16;
+                let height = usize::from(mb_h) * 16;
+                assert_eq!(vbuf.get_dimensions(0), (width, height));
+                let off = vbuf.get_offset(0);
+                let stride = vbuf.get_stride(0);
+                for line in vbuf.get_data()[off..].chunks(stride).take(height) {
+                    assert!(line[..width].iter().all(|&pix| pix == 128));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_vp6_probe_output_format() {
+        let vinfo = NAVideoInfo::new(176, 144, false, YUV420_FORMAT);
+        let info = NACodecInfo::new("vp6f", NACodecTypeInfo::Video(vinfo), Some(vec![0x62])).into_ref();
+        let probed = vp6_probe_output_format(&info).unwrap();
+        assert_eq!((probed.get_width(), probed.get_height(), probed.is_flipped()), (170, 142, false));
+        assert!(probed.get_format() == YUV420_FORMAT);
+
+        let mut dec = VP56Decoder::new(6, false, probed.is_flipped());
+        let mut supp = NADecoderSupport::new();
+        dec.init(&mut supp, probed).unwrap();
+        let frm = TestFrame { mb_w: 11, mb_h: 9, disp_w: 11, disp_h: 9, ..Default::default() };
+        let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(&frm, None), &mut VP6BR::new()).unwrap();
+        assert!(buf.get_vbuf().unwrap().get_info() == probed);
+
+        let info = NACodecInfo::new("vp6a", NACodecTypeInfo::Video(vinfo), None).into_ref();
+        let probed = vp6_probe_output_format(&info).unwrap();
+        assert_eq!((probed.get_width(), probed.get_height()), (176, 144));
+        assert!(probed.get_format() == VP_YUVA420_FORMAT);
+
+        let info = NACodecInfo::new("vp6", NACodecTypeInfo::Video(vinfo), None).into_ref();
+        assert!(vp6_probe_output_format(&info).unwrap().is_flipped());
+
+        let info = NACodecInfo::new("vp8", NACodecTypeInfo::Video(vinfo), None).into_ref();
+        assert!(vp6_probe_output_format(&info).is_err());
+
+        // cropping more than the coded size
+        for &edata in [0x11, 0x10, 0x01].iter() {
+            let vinfo = NAVideoInfo::new(0, 0, false, YUV420_FORMAT);
+            let info = NACodecInfo::new("vp6f", NACodecTypeInfo::Video(vinfo), Some(vec![edata])).into_ref();
+            assert_eq!(vp6_probe_output_format(&info).err(), Some(DecoderError::InvalidData));
+        }
+    }
+
+    #[test]
+    fn test_vp6_decode_into() {
+        let intra = TestFrame { mb_w: 11, mb_h: 9, disp_w: 10, disp_h: 9, ..Default::default() };
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(176, 144, false);
+        let (mut dec2, mut br2, mut supp2) = init_decoder(176, 144, false);
+        let mut out = alloc_video_buffer(NAVideoInfo::new(176, 144, false, YUV420_FORMAT), 6).unwrap().get_vbuf().unwrap();
+        let same_picture = |buf: &NAVideoBufferRef<u8>, out: &NAVideoBuffer<u8>| {
+            assert_eq!(out.get_crop_rect(), buf.get_crop_rect());
+            for plane in 0..3 {
+                let (w, h) = buf.get_dimensions(plane);
+                let ref_lines = buf.get_data()[buf.get_offset(plane)..].chunks(buf.get_stride(plane));
+                let out_lines = out.get_data()[out.get_offset(plane)..].chunks(out.get_stride(plane));
+                for (rline, oline) in ref_lines.zip(out_lines).take(h) {
+                    assert_eq!(&rline[..w], &oline[..w]);
+                }
+            }
+        };
+        for frm in [intra, inter].iter() {
+            let pkt = gen_packet(frm, None);
+            let (buf, ftype) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+            assert_eq!(dec2.decode_into(&mut supp2, &pkt, &mut br2, &mut out).unwrap(), ftype);
+            same_picture(&buf.get_vbuf().unwrap(), &out);
+        }
+
+        // mismatching buffers are rejected before anything is decoded, including keyframes changing the picture size
+        let session = dec2.get_session_stats();
+        let mut small = alloc_video_buffer(NAVideoInfo::new(64, 48, false, YUV420_FORMAT), 4).unwrap().get_vbuf().unwrap();
+        assert_eq!(dec2.decode_into(&mut supp2, &gen_packet(&inter, None), &mut br2, &mut small).err(), Some(DecoderError::InvalidData));
+        let resized = TestFrame { interlaced: true, ..Default::default() };
+        assert_eq!(dec2.decode_into(&mut supp2, &gen_packet(&resized, None), &mut br2, &mut out).err(), Some(DecoderError::InvalidData));
+        assert_eq!(dec2.get_session_stats().frames, session.frames);
+        assert!(!br2.interlaced);
+
+        let pkt = gen_packet(&inter, None);
+        let (buf, _) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+        assert_eq!(dec2.decode_into(&mut supp2, &pkt, &mut br2, &mut out).unwrap(), FrameType::P);
+        same_picture(&buf.get_vbuf().unwrap(), &out);
+    }
+
+    #[test]
+    fn test_vp6_luma_only() {
+        let intra = TestFrame::default();
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        let (mut ldec, mut lbr, mut lsupp) = init_decoder(64, 48, false);
+        ldec.set_luma_only(true);
+        for frm in [intra, inter, inter].iter() {
+            let pkt = gen_packet(frm, None);
+            let (buf, _) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+            let (lbuf, _) = ldec.decode_frame(&mut lsupp, &pkt, &mut lbr).unwrap();
+            let buf = buf.get_vbuf().unwrap();
+            let lbuf = lbuf.get_vbuf().unwrap();
+            let luma = buf.get_data()[buf.get_offset(0)..].chunks(buf.get_stride(0)).take(48);
+            let lluma = lbuf.get_data()[lbuf.get_offset(0)..].chunks(lbuf.get_stride(0)).take(48);
+            for (line, lline) in luma.zip(lluma) {
+                assert_eq!(&line[..64], &lline[..64]);
+            }
+            for plane in 1..3 {
+                for line in lbuf.get_data()[lbuf.get_offset(plane)..].chunks(lbuf.get_stride(plane)).take(24) {
+                    assert!(line[..32].iter().all(|&pix| pix == 128));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_vp6_max_dimensions() {
+        let mut dec = VP56Decoder::new(6, false, false);
+        let mut supp = NADecoderSupport::new();
+        dec.set_max_dimensions(Some((1920, 1088)));
+        let ret = dec.init(&mut supp, NAVideoInfo::new(65535, 65535, false, YUV420_FORMAT));
+        assert_eq!(ret.err(), Some(DecoderError::InvalidData));
+
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        dec.set_max_dimensions(Some((1920, 1088)));
+        let huge = TestFrame { mb_w: 255, mb_h: 255, disp_w: 255, disp_h: 255, ..Default::default() };
+        let ret = dec.decode_frame(&mut supp, &gen_packet(&huge, None), &mut br);
+        assert_eq!(ret.err(), Some(DecoderError::InvalidData));
+        let small = TestFrame { mb_w: 4, mb_h: 3, disp_w: 4, disp_h: 3, ..Default::default() };
+        let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(&small, None), &mut br).unwrap();
+        assert_eq!(buf.get_vbuf().unwrap().get_dimensions(0), (64, 48));
+    }
+
+    #[test]
+    fn test_vp6_profile() {
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        assert_eq!(br.get_profile(), None);
+        dec.decode_frame(&mut supp, &gen_packet(&TestFrame::default(), None), &mut br).unwrap();
+        assert_eq!(br.get_profile(), Some((VP6_ADVANCED_PROFILE, VERSION_VP62)));
+        let inter = TestFrame { intra: false, ..Default::default() };
+        dec.decode_frame(&mut supp, &gen_packet(&inter, None), &mut br).unwrap();
+        assert_eq!(br.get_profile(), Some((VP6_ADVANCED_PROFILE, VERSION_VP62)));
+    }
+
+    #[test]
+    fn test_vp6_stream_info() {
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        assert_eq!(br.get_stream_info(), None);
+        let frm = TestFrame { version: VERSION_VP60, interlaced: true, ..Default::default() };
+        dec.decode_frame(&mut supp, &gen_packet(&frm, None), &mut br).unwrap();
+        assert_eq!(br.get_stream_info(), Some(VP6StreamInfo { version: VERSION_VP60, advanced: true, interlaced: true }));
+        dec.decode_frame(&mut supp, &gen_packet(&TestFrame::default(), None), &mut br).unwrap();
+        assert_eq!(br.get_stream_info(), Some(VP6StreamInfo { version: VERSION_VP62, advanced: true, interlaced: false }));
+    }
+
+    #[test]
+    fn test_vp6_rgb_output() {
+        let intra = TestFrame { mb_w: 11, mb_h: 9, disp_w: 10, disp_h: 9, ..Default::default() };
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(176, 144, false);
+        let (mut dec_rgb, mut br_rgb, mut supp_rgb) = init_decoder(176, 144, false);
+        dec_rgb.set_output_format(VP56OutputFormat::RGB24);
+        let (mut dec_into, mut br_into, mut supp_into) = init_decoder(176, 144, false);
+        dec_into.set_output_format(VP56OutputFormat::RGB24);
+        let mut out = alloc_video_buffer(NAVideoInfo::new(176, 144, false, RGB24_FORMAT), 0).unwrap().get_vbuf().unwrap();
+        for frm in [intra, inter].iter() {
+            let pkt = gen_packet(frm, None);
+            let (buf, _) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+            let yuv = buf.get_vbuf().unwrap();
+            let (buf, _) = dec_rgb.decode_frame(&mut supp_rgb, &pkt, &mut br_rgb).unwrap();
+            assert!(matches!(buf, NABufferType::VideoPacked(_)));
+            let rgb = buf.get_vbuf().unwrap();
+            assert!(rgb.get_info() == NAVideoInfo::new(176, 144, false, RGB24_FORMAT));
+            assert_eq!(rgb.get_crop_rect(), Some((0, 0, 160, 144)));
+
+            let stride = rgb.get_stride(0);
+            for y in 0..144 {
+                for x in 0..176 {
+                    let luma = yuv.get_data()[yuv.get_offset(0) + x + y * yuv.get_stride(0)];
+                    let u = yuv.get_data()[yuv.get_offset(1) + x / 2 + y / 2 * yuv.get_stride(1)];
+                    let v = yuv.get_data()[yuv.get_offset(2) + x / 2 + y / 2 * yuv.get_stride(2)];
+                    let ref_pix = ref_yuv2rgb(luma, u, v);
+                    for (&pix, &ref_val) in rgb.get_data()[x * 3 + y * stride..][..3].iter().zip(ref_pix.iter()) {
+                        assert!((i16::from(pix) - i16::from(ref_val)).abs() <= 1);
+                    }
+                }
+            }
+
+            dec_into.decode_into(&mut supp_into, &pkt, &mut br_into, &mut out).unwrap();
+            assert_eq!(out.get_data(), rgb.get_data());
+        }
+    }
+
+    #[test]
+    fn test_vp6_error_concealment() {
+        // every Huffman code is end of block, 18 bytes are enough to code all macroblocks
+        static HUFF_DATA: [u8; 24] = [0xFF; 24];
+        let intra = TestFrame { huff_data: Some(&HUFF_DATA), ..Default::default() };
+        let intra_trunc = TestFrame { huff_data: Some(&HUFF_DATA[..8]), ..Default::default() };
+        let inter_trunc = TestFrame { intra: false, huff_data: Some(&HUFF_DATA[..1]), ..Default::default() };
+
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        assert_eq!(dec.decode_frame(&mut supp, &gen_packet(&intra_trunc, None), &mut br).err(), Some(DecoderError::ShortData));
+        let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+        assert_eq!(dec.get_frame_stats().concealed_mbs, 0);
+        let ref_frame = buf.get_vbuf().unwrap();
+        assert_eq!(dec.decode_frame(&mut supp, &gen_packet(&inter_trunc, None), &mut br).err(), Some(DecoderError::ShortData));
+
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        dec.set_error_concealment(true);
+        let (buf, ftype) = dec.decode_frame(&mut supp, &gen_packet(&intra_trunc, None), &mut br).unwrap();
+        assert_eq!(ftype, FrameType::I);
+        assert_eq!(dec.get_frame_stats().concealed_mbs, 7);
+        // the first five macroblocks are decoded, the rest is filled with grey
+        let vbuf = buf.get_vbuf().unwrap();
+        let off = vbuf.get_offset(1);
+        let stride = vbuf.get_stride(1);
+        for (mb_y, line) in vbuf.get_data()[off..].chunks(stride * 8).take(3).enumerate() {
+            for mb_x in 0..4 {
+                let expected = if mb_x + mb_y * 4 < 5 { ref_frame.get_data()[off + mb_x * 8 + mb_y * 8 * stride] } else { 128 };
+                assert_eq!(line[mb_x * 8 + 7 * stride + 7], expected);
+            }
+        }
+        assert_ne!(ref_frame.get_data()[off], 128);
+
+        let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+        assert_eq!(buf.get_vbuf().unwrap().get_data(), ref_frame.get_data());
+        let (buf, ftype) = dec.decode_frame(&mut supp, &gen_packet(&inter_trunc, None), &mut br).unwrap();
+        assert_eq!(ftype, FrameType::P);
+        assert!(dec.get_frame_stats().concealed_mbs > 0);
+        assert_eq!(buf.get_vbuf().unwrap().get_data(), ref_frame.get_data());
+    }
+
+    #[test]
+    fn test_vp6_partial_last_frame() {
+        static HUFF_DATA: [u8; 24] = [0xFF; 24];
+        let intra = TestFrame { huff_data: Some(&HUFF_DATA), ..Default::default() };
+        let pkt = gen_packet(&intra, None);
+        // cut the packet so that only 8 bytes of coefficient data remain
+        let trunc = &pkt[..pkt.len() - 16];
+
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        assert_eq!(dec.decode_frame(&mut supp, trunc, &mut br).err(), Some(DecoderError::ShortData));
+        let (_, ftype) = dec.decode_last_frame(&mut supp, trunc, &mut br).unwrap();
+        assert_eq!(ftype, FrameType::I);
+        assert_eq!(dec.get_frame_stats().concealed_mbs, 7);
+        assert_eq!(dec.decode_frame(&mut supp, trunc, &mut br).err(), Some(DecoderError::ShortData));
+        // a packet with a broken header is still rejected
+        assert!(dec.decode_last_frame(&mut supp, &pkt[..3], &mut br).is_err());
+        let (_, ftype) = dec.decode_last_frame(&mut supp, &pkt, &mut br).unwrap();
+        assert_eq!(ftype, FrameType::I);
+        assert_eq!(dec.get_frame_stats().concealed_mbs, 0);
+    }
+
+    #[test]
+    fn test_vp6_timing() {
+        let intra = TestFrame::default();
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+        assert_eq!(dec.get_timing(), VP56Timing::default());
+
+        dec.set_timing(true);
+        dec.set_postproc_level(VP_MAX_POSTPROC_LEVEL);
+        dec.set_output_format(VP56OutputFormat::RGB24);
+        for frm in [intra, inter].iter() {
+            dec.decode_frame(&mut supp, &gen_packet(frm, None), &mut br).unwrap();
+            let timing = dec.get_timing();
+            assert!(timing.mbs > Duration::ZERO);
+            assert!(timing.postproc > Duration::ZERO);
+            assert!(timing.total >= timing.mbs + timing.postproc);
+            assert_eq!(timing.parse + timing.mbs + timing.postproc, timing.total);
+        }
+
+        dec.set_timing(false);
+        dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+        assert_eq!(dec.get_timing(), VP56Timing::default());
+    }
+
+    #[test]
+    fn test_vp6_export_motion() {
+        let intra = TestFrame { mb_w: 5, mb_h: 2, disp_w: 5, disp_h: 2, ..Default::default() };
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(80, 32, false);
+        let (mut dec2, mut br2, mut supp2) = init_decoder(80, 32, false);
+        dec.set_motion_export(true);
+        for (frm, ref_id) in [(intra, 0), (inter, VP_REF_INTER)].iter() {
+            let pkt = gen_packet(frm, None);
+            let (buf, _) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+            let (buf2, _) = dec2.decode_frame(&mut supp2, &pkt, &mut br2).unwrap();
+            assert_eq!(buf.get_vbuf().unwrap().get_data(), buf2.get_vbuf().unwrap().get_data());
+            let (w, h, mvs) = dec.get_motion_vectors().unwrap();
+            assert_eq!((w, h, mvs.len()), (5, 2, 10));
+            assert!(mvs.iter().all(|mb| mb.mv == MV::default() && mb.ref_id == *ref_id));
+            assert!(dec2.get_motion_vectors().is_none());
+        }
+    }
+
+    #[test]
+    fn test_vp6_keyframe_index() {
+        let intra = TestFrame::default();
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let stream = [intra, inter, inter, intra, inter, intra, inter, inter];
+        let mut index = VP6KeyframeIndex::new(false);
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        for (i, frm) in stream.iter().enumerate() {
+            let pkt = gen_packet(frm, None);
+            assert_eq!(index.add_packet(i as u64 * 40, &pkt).unwrap(), frm.intra);
+            let (_, ftype) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+            assert_eq!(ftype == FrameType::I, frm.intra);
+        }
+        let entries: Vec<(usize, u64)> = index.get_entries().iter().map(|e| (e.pkt_no, e.ts)).collect();
+        assert_eq!(entries, vec![(0, 0), (3, 120), (5, 200)]);
+        assert_eq!(index.find_keyframe(0), Some(VP6KeyframeEntry { pkt_no: 0, ts: 0 }));
+        assert_eq!(index.find_keyframe(199), Some(VP6KeyframeEntry { pkt_no: 3, ts: 120 }));
+        assert_eq!(index.find_keyframe(1000), Some(VP6KeyframeEntry { pkt_no: 5, ts: 200 }));
+
+        let mut index = VP6KeyframeIndex::new(true);
+        assert!(!index.add_packet(0, &gen_packet(&inter, Some(&intra))).unwrap());
+        assert!(index.add_packet(1, &gen_packet(&intra, Some(&inter))).unwrap());
+        assert!(index.add_packet(2, &[0, 0, 1]).is_err());
+        assert_eq!(index.get_entries(), &[VP6KeyframeEntry { pkt_no: 1, ts: 1 }]);
+        assert_eq!(index.find_keyframe(0), None);
+    }
+
+    #[test]
+    fn test_vp6_negotiate_output_format() {
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        let ret = dec.negotiate_output_format(NAVideoInfo::new(64, 48, false, RGB24_FORMAT));
+        assert!(ret == NAVideoInfo::new(64, 48, false, RGB24_FORMAT));
+        let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(&TestFrame::default(), None), &mut br).unwrap();
+        assert!(buf.get_vbuf().unwrap().get_info() == ret);
+
+        let ret = dec.negotiate_output_format(NAVideoInfo::new(64, 48, false, RGB565_FORMAT));
+        assert!(ret == NAVideoInfo::new(64, 48, false, YUV420_FORMAT));
+        let (buf, _) = dec.decode_frame(&mut supp, &gen_packet(&TestFrame::default(), None), &mut br).unwrap();
+        assert!(buf.get_vbuf().unwrap().get_info() == ret);
+
+        let (mut dec, _, _) = init_decoder(64, 48, true);
+        let ret = dec.negotiate_output_format(NAVideoInfo::new(64, 48, false, YUV420_FORMAT));
+        assert!(ret.get_format() == VP_YUVA420_FORMAT);
+    }
+
+    #[test]
+    fn test_vp6_probe() {
+        let intra = TestFrame::default();
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        assert!(!dec.probe(&gen_packet(&inter, None), &br).unwrap());
+        assert!(dec.probe(&gen_packet(&intra, None), &br).unwrap());
+        // probing does not change the parser state
+        assert!(dec.probe(&gen_packet(&TestFrame { interlaced: true, ..intra }, None), &br).unwrap());
+        assert!(!br.interlaced);
+        assert_eq!(br.get_profile(), None);
+        // probing does not provide a reference frame
+        assert!(!dec.probe(&gen_packet(&inter, None), &br).unwrap());
+        dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+        assert!(dec.probe(&gen_packet(&inter, None), &br).unwrap());
+
+        let mut seed = 0x1234_5678u32;
+        let garbage: Vec<u8> = (0..64).map(|_| { seed = seed.wrapping_mul(1664525).wrapping_add(1013904223); (seed >> 24) as u8 }).collect();
+        let (mut dec, br, _) = init_decoder(64, 48, false);
+        assert!(!dec.probe(&garbage, &br).unwrap());
+        assert!(!dec.probe(&[0x00, 0x30], &br).unwrap());
+
+        dec.set_max_dimensions(Some((32, 32)));
+        assert!(!dec.probe(&gen_packet(&intra, None), &br).unwrap());
+
+        let (dec, br, _) = init_decoder(64, 48, true);
+        assert!(dec.probe(&gen_packet(&intra, Some(&intra)), &br).unwrap());
+        let small = TestFrame { mb_w: 2, mb_h: 2, ..Default::default() };
+        assert!(!dec.probe(&gen_packet(&intra, Some(&small)), &br).unwrap());
+    }
+
+    #[test]
+    fn test_vp6_alloc_failure() {
+        let mut dec = VP56Decoder::new(6, false, false);
+        let mut supp = NADecoderSupport::new();
+        let vinfo = NAVideoInfo::new(64, 48, false, YUV420_FORMAT);
+        supp.pool_u8.fail_next_alloc();
+        assert_eq!(dec.init(&mut supp, vinfo).err(), Some(DecoderError::AllocError));
+        dec.init(&mut supp, vinfo).unwrap();
+
+        let mut br = VP6BR::new();
+        let pkt = gen_packet(&TestFrame::default(), None);
+        supp.pool_u8.fail_next_alloc();
+        assert_eq!(dec.decode_frame(&mut supp, &pkt, &mut br).err(), Some(DecoderError::AllocError));
+        let (_, ftype) = dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+        assert_eq!(ftype, FrameType::I);
+    }
+
+    #[test]
+    fn test_vp6_malformed_input() {
+        // packets that used to trigger out-of-bounds accesses
+        let pkts: [&[u8]; 3] = [
+            &[0, 0, 27, 204, 0, 25, 16, 0, 0, 8, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 193, 0, 9, 16, 0, 0, 0],
+            &[0, 0, 33, 65, 70, 0, 15, 3, 4, 3, 4, 0, 128, 0, 0, 0, 0, 0, 127, 255, 255, 255, 255, 255, 255, 255, 255, 191, 255, 255, 255, 255, 255, 255, 255, 255, 65, 70, 0, 15, 3, 4, 3, 4, 0, 128, 0],
+            &[0, 0, 2, 65, 70, 0, 15, 3, 4, 3, 4, 0],
+        ];
+        for pkt in pkts.iter() {
+            let (mut dec, mut br, mut supp) = init_decoder(64, 48, true);
+            assert!(dec.decode_frame(&mut supp, pkt, &mut br).is_err());
+            assert!(dec.decode_checked(&mut supp, pkt, &mut br).is_err());
+        }
+
+        let frames = [TestFrame::default(), TestFrame { intra: false, ..Default::default() },
+                      TestFrame { huff_data: Some(&[0xFF; 18]), ..Default::default() },
+                      TestFrame { intra: false, huff_data: Some(&[0xFF; 18]), ..Default::default() }];
+        let mut seed = 0x1234_5678u32;
+        let mut rnd = move || { seed = seed.wrapping_mul(1664525).wrapping_add(1013904223); (seed >> 8) as usize };
+        for &alpha in [false, true].iter() {
+            let (mut dec, mut br, mut supp) = init_decoder(64, 48, alpha);
+            for _ in 0..300 {
+                let frm = &frames[rnd() % frames.len()];
+                let mut pkt = gen_packet(frm, if alpha { Some(frm) } else { None });
+                for _ in 0..1 + rnd() % 4 {
+                    match rnd() % 3 {
+                        0 => { let pos = rnd() % pkt.len(); pkt[pos] ^= 1 << (rnd() % 8); },
+                        1 => { let pos = rnd() % pkt.len(); pkt[pos] = rnd() as u8; },
+                        _ => { let len = rnd() % pkt.len(); pkt.truncate(len.max(1)); },
+                    };
+                }
+                // called directly so that any panic fails the test
+                let _ = dec.decode_frame(&mut supp, &pkt, &mut br);
+                let keyframe = gen_packet(&frames[0], if alpha { Some(&frames[0]) } else { None });
+                dec.decode_frame(&mut supp, &keyframe, &mut br).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_vp6_ignore_golden_updates() {
+        let intra = TestFrame::default();
+        let golden = TestFrame { intra: false, golden: true, ..Default::default() };
+        for &ignore in [false, true].iter() {
+            let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+            dec.set_ignore_golden_updates(ignore);
+            let key = dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap().0.get_vbuf().unwrap();
+            let (_, ftype) = dec.decode_frame(&mut supp, &gen_packet(&golden, None), &mut br).unwrap();
+            assert_eq!(ftype, FrameType::P);
+            assert_eq!(dec.get_frame_stats().golden_update, !ignore);
+            // the keyframe is referenced by the pool and here, and by the decoder only if it remains the golden frame
+            assert_eq!(key.get_num_refs(), if ignore { 3 } else { 2 });
+        }
+    }
+
+    #[test]
+    fn test_vp6_session_stats() {
+        let intra = TestFrame { quant: 10, ..Default::default() };
+        let inter = TestFrame { intra: false, quant: 20, ..Default::default() };
+        let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+        assert_eq!(dec.get_session_stats(), VP56SessionStats::default());
+        assert_eq!(dec.get_session_stats().avg_quant(), None);
+        // a frame failing to decode is not counted
+        assert!(dec.decode_frame(&mut supp, &gen_packet(&inter, None), &mut br).is_err());
+
+        let mut bytes = 0;
+        for frm in [intra, inter, inter, intra, inter].iter() {
+            let pkt = gen_packet(frm, None);
+            bytes += pkt.len();
+            dec.decode_frame(&mut supp, &pkt, &mut br).unwrap();
+        }
+        let stats = dec.get_session_stats();
+        assert_eq!((stats.frames, stats.keyframes, stats.bytes), (5, 2, bytes));
+        assert_eq!(stats.quant_sum, 80);
+        assert_eq!(stats.avg_quant(), Some(16.0));
+        assert_eq!(stats.decode_time, Duration::ZERO);
+
+        dec.flush();
+        assert_eq!(dec.get_session_stats(), VP56SessionStats::default());
+        dec.set_timing(true);
+        dec.decode_frame(&mut supp, &gen_packet(&intra, None), &mut br).unwrap();
+        let stats = dec.get_session_stats();
+        assert_eq!((stats.frames, stats.keyframes), (1, 1));
+        assert_eq!(stats.decode_time, dec.get_timing().total);
+    }
+
+    #[test]
+    fn test_vp6_shared_pools() {
+        let mut hw = HuffWriter::new();
+        for mb in 0..12 {
+            for blk_no in 0..4 {
+                hw.put_block(0, if ((mb + blk_no) & 1) == 0 { 2 } else { -3 }, 4);
+            }
+            hw.put_block(1, 1, 0);
+            hw.put_block(1, -1, 0);
+        }
+        let huff_data = hw.finish();
+        let intra = TestFrame { huff_data: Some(&huff_data), ..Default::default() };
+        let inter = TestFrame { intra: false, ..Default::default() };
+        let streams = [vec![gen_packet(&intra, None), gen_packet(&inter, None), gen_packet(&inter, None)],
+                       vec![gen_packet(&TestFrame { quant: 10, ..Default::default() }, None), gen_packet(&inter, None)]];
+        let mut ref_frames = Vec::new();
+        for pkts in streams.iter() {
+            let (mut dec, mut br, mut supp) = init_decoder(64, 48, false);
+            let frames: Vec<Vec<u8>> = pkts.iter().map(|pkt| dec.decode_frame(&mut supp, pkt, &mut br).unwrap().0.get_vbuf().unwrap().get_data().to_vec()).collect();
+            ref_frames.push(frames);
+        }
+        assert_ne!(ref_frames[0][0], ref_frames[1][0]);
+
+        let pools = NADecoderSupport::new();
+        std::thread::scope(|scope| {
+            for (pkts, frames) in streams.iter().zip(ref_frames.iter()) {
+                let mut supp = NADecoderSupport::with_shared_pools(&pools.pool_u8, &pools.pool_u16, &pools.pool_u32);
+                assert!(supp.pool_u8.is_shared_with(&pools.pool_u8));
+                scope.spawn(move || {
+                    let mut dec = VP56Decoder::new(6, false, false);
+                    dec.init(&mut supp, NAVideoInfo::new(64, 48, false, YUV420_FORMAT)).unwrap();
+                    let mut br = VP6BR::new();
+                    let mut prev: Option<(NAVideoBufferRef<u8>, &Vec<u8>)> = None;
+                    for _ in 0..20 {
+                        for (pkt, ref_frame) in pkts.iter().zip(frames.iter()) {
+                            let vbuf = dec.decode_frame(&mut supp, pkt, &mut br).unwrap().0.get_vbuf().unwrap();
+                            assert_eq!(vbuf.get_data(), &ref_frame[..]);
+                            // the previous output is still intact while the other decoder runs
+                            if let Some((pbuf, pref)) = prev.replace((vbuf, ref_frame)) {
+                                assert_eq!(pbuf.get_data(), &pref[..]);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        // both decoders released their frames and reservations
+        assert_eq!(pools.pool_u8.get_num_used(), 0);
+        assert_eq!(pools.pool_u8.buffer_count().0, 6);
+    }
+}
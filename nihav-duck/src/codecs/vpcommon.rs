@@ -98,7 +98,7 @@ pub struct BoolCoder<'a> {
 #[allow(dead_code)]
 impl<'a> BoolCoder<'a> {
     pub fn new(src: &'a [u8]) -> DecoderResult<Self> {
-        if src.len() < 3 { return Err(DecoderError::ShortData); }
+        if src.len() < 4 { return Err(DecoderError::ShortData); }
         let value = (u32::from(src[0]) << 24) | (u32::from(src[1]) << 16) | (u32::from(src[2]) << 8) | u32::from(src[3]);
         Ok(Self { src, pos: 4, value, range: 255, bits: 8 })
     }
@@ -206,7 +206,7 @@ const C6S2: i32 = 25080;
 const C7S1: i32 = 12785;
 
 fn mul16(a: i32, b: i32) -> i32 {
-    (a * b) >> 16
+    a.wrapping_mul(b) >> 16
 }
 
 macro_rules! idct_step {
@@ -221,8 +221,8 @@ macro_rules! idct_step {
         let t_b1 = mul16(C4S4, t_b - t_d);
         let t_c  = t_a + t_c;
         let t_d  = t_b + t_d;
-        let t_e  = mul16(C4S4, i32::from($s0 + $s4)) + $bias;
-        let t_f  = mul16(C4S4, i32::from($s0 - $s4)) + $bias;
+        let t_e  = mul16(C4S4, i32::from($s0.wrapping_add($s4))) + $bias;
+        let t_f  = mul16(C4S4, i32::from($s0.wrapping_sub($s4))) + $bias;
         let t_g  = mul16(C2S6, i32::from($s2)) + mul16(C6S2, i32::from($s6));
         let t_h  = mul16(C6S2, i32::from($s2)) - mul16(C2S6, i32::from($s6));
         let t_e1 = t_e  - t_g;
@@ -277,7 +277,7 @@ pub fn vp_put_block(coeffs: &mut [i16; 64], bx: usize, by: usize, plane: usize,
     let mut off = frm.offset[plane] + bx * 8 + by * 8 * frm.stride[plane];
     for y in 0..8 {
         for x in 0..8 {
-            frm.data[off + x] = (coeffs[x + y * 8] + 128).min(255).max(0) as u8;
+            frm.data[off + x] = coeffs[x + y * 8].wrapping_add(128).clamp(0, 255) as u8;
         }
         off += frm.stride[plane];
     }
@@ -288,7 +288,7 @@ pub fn vp_put_block_ilace(coeffs: &mut [i16; 64], bx: usize, by: usize, plane: u
     let mut off = frm.offset[plane] + bx * 8 + ((by & !1) * 8 + (by & 1)) * frm.stride[plane];
     for y in 0..8 {
         for x in 0..8 {
-            frm.data[off + x] = (coeffs[x + y * 8] + 128).min(255).max(0) as u8;
+            frm.data[off + x] = coeffs[x + y * 8].wrapping_add(128).clamp(0, 255) as u8;
         }
         off += frm.stride[plane] * 2;
     }
@@ -296,7 +296,7 @@ pub fn vp_put_block_ilace(coeffs: &mut [i16; 64], bx: usize, by: usize, plane: u
 
 pub fn vp_put_block_dc(coeffs: &mut [i16; 64], bx: usize, by: usize, plane: usize, frm: &mut NASimpleVideoFrame<u8>) {
     vp_idct_dc(coeffs);
-    let dc = (coeffs[0] + 128).min(255).max(0) as u8;
+    let dc = coeffs[0].wrapping_add(128).clamp(0, 255) as u8;
     let mut off = frm.offset[plane] + bx * 8 + by * 8 * frm.stride[plane];
     for _ in 0..8 {
         for x in 0..8 {
@@ -311,7 +311,7 @@ pub fn vp_add_block(coeffs: &mut [i16; 64], bx: usize, by: usize, plane: usize,
     let mut off = frm.offset[plane] + bx * 8 + by * 8 * frm.stride[plane];
     for y in 0..8 {
         for x in 0..8 {
-            frm.data[off + x] = (coeffs[x + y * 8] + i16::from(frm.data[off + x])).min(255).max(0) as u8;
+            frm.data[off + x] = coeffs[x + y * 8].wrapping_add(i16::from(frm.data[off + x])).clamp(0, 255) as u8;
         }
         off += frm.stride[plane];
     }
@@ -322,7 +322,7 @@ pub fn vp_add_block_ilace(coeffs: &mut [i16; 64], bx: usize, by: usize, plane: u
     let mut off = frm.offset[plane] + bx * 8 + ((by & !1) * 8 + (by & 1)) * frm.stride[plane];
     for y in 0..8 {
         for x in 0..8 {
-            frm.data[off + x] = (coeffs[x + y * 8] + i16::from(frm.data[off + x])).min(255).max(0) as u8;
+            frm.data[off + x] = coeffs[x + y * 8].wrapping_add(i16::from(frm.data[off + x])).clamp(0, 255) as u8;
         }
         off += frm.stride[plane] * 2;
     }
@@ -334,12 +334,74 @@ pub fn vp_add_block_dc(coeffs: &mut [i16; 64], bx: usize, by: usize, plane: usiz
     let mut off = frm.offset[plane] + bx * 8 + by * 8 * frm.stride[plane];
     for _ in 0..8 {
         for x in 0..8 {
-            frm.data[off + x] = (dc + i16::from(frm.data[off + x])).min(255).max(0) as u8;
+            frm.data[off + x] = dc.wrapping_add(i16::from(frm.data[off + x])).clamp(0, 255) as u8;
         }
         off += frm.stride[plane];
     }
 }
 
+// perceptual hash: luma downscaled to 32x32, lowest 8x8 DCT coefficients compared to their median,
+// similar pictures should have fingerprints with small Hamming distance;
+// only the visible area (the crop rectangle if it is set) is taken into account
+pub fn vp_fingerprint(buf: &NAVideoBuffer<u8>) -> u64 {
+    const FP_SIZE: usize = 32;
+    let (full_w, full_h) = buf.get_dimensions(0);
+    let (cx, cy, w, h) = buf.get_crop_rect().unwrap_or((0, 0, full_w, full_h));
+    if w == 0 || h == 0 {
+        return 0;
+    }
+    let stride = buf.get_stride(0);
+    let data = &buf.get_data()[buf.get_offset(0) + cx + cy * stride..];
+
+    let mut small = [0.0f32; FP_SIZE * FP_SIZE];
+    for (y, row) in small.chunks_mut(FP_SIZE).enumerate() {
+        let y0 = y * h / FP_SIZE;
+        let y1 = ((y + 1) * h / FP_SIZE).max(y0 + 1);
+        for (x, el) in row.iter_mut().enumerate() {
+            let x0 = x * w / FP_SIZE;
+            let x1 = ((x + 1) * w / FP_SIZE).max(x0 + 1);
+            let mut sum = 0u32;
+            for line in data[y0 * stride..].chunks(stride).take(y1 - y0) {
+                for &pix in line[x0..x1].iter() {
+                    sum += u32::from(pix);
+                }
+            }
+            *el = (sum as f32) / (((x1 - x0) * (y1 - y0)) as f32);
+        }
+    }
+
+    let mut basis = [[0.0f32; FP_SIZE]; 8];
+    for (i, row) in basis.iter_mut().enumerate() {
+        for (j, el) in row.iter_mut().enumerate() {
+            *el = (std::f32::consts::PI * (i as f32) * ((2 * j + 1) as f32) / ((2 * FP_SIZE) as f32)).cos();
+        }
+    }
+    let mut tmp = [[0.0f32; 8]; FP_SIZE];
+    for (row, dst) in small.chunks(FP_SIZE).zip(tmp.iter_mut()) {
+        for (el, bvec) in dst.iter_mut().zip(basis.iter()) {
+            *el = row.iter().zip(bvec.iter()).fold(0.0, |acc, (a, b)| acc + a * b);
+        }
+    }
+    let mut coeffs = [0.0f32; 64];
+    for (v, bvec) in basis.iter().enumerate() {
+        for u in 0..8 {
+            coeffs[v * 8 + u] = tmp.iter().zip(bvec.iter()).fold(0.0, |acc, (a, b)| acc + a[u] * b);
+        }
+    }
+
+    let mut sorted = [0.0f32; 63];
+    sorted.copy_from_slice(&coeffs[1..]);
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = sorted[31];
+    let mut fp = 0u64;
+    for (i, &coef) in coeffs.iter().enumerate().skip(1) {
+        if coef > median {
+            fp |= 1 << i;
+        }
+    }
+    fp
+}
+
 pub fn vp31_loop_filter(data: &mut [u8], mut off: usize, step: usize, stride: usize,
                         len: usize, loop_str: i16) {
     for _ in 0..len {
@@ -358,10 +420,230 @@ pub fn vp31_loop_filter(data: &mut [u8], mut off: usize, step: usize, stride: us
             }
         }
         if diff != 0 {
-            data[off - step] = (b + diff).max(0).min(255) as u8;
-            data[off]        = (c - diff).max(0).min(255) as u8;
+            data[off - step] = (b + diff).clamp(0, 255) as u8;
+            data[off]        = (c - diff).clamp(0, 255) as u8;
         }
 
         off += stride;
     }
 }
+
+pub fn vp_deblock_plane(data: &mut [u8], off: usize, stride: usize, w: usize, h: usize, loop_str: i16) {
+    if loop_str == 0 {
+        return;
+    }
+    for x in (8..w).step_by(8) {
+        vp31_loop_filter(data, off + x, 1, stride, h, loop_str);
+    }
+    for y in (8..h).step_by(8) {
+        vp31_loop_filter(data, off + y * stride, stride, 1, w, loop_str);
+    }
+}
+
+pub const VP_MAX_POSTPROC_LEVEL: u8 = 3;
+
+// postprocessing levels:
+//  0 - none
+//  1 - luma plane deblocking with the strength derived from the frame quantiser
+//  2 - same for all planes
+//  3 - all planes with doubled strength, removes more blocking at the cost of detail
+pub fn vp_postprocess(buf: &mut NAVideoBuffer<u8>, level: u8, loop_str: i16) {
+    let nplanes = match level {
+            0 => return,
+            1 => 1,
+            _ => 3,
+        };
+    let loop_str = if level >= 3 { loop_str * 2 } else { loop_str };
+    for plane in 0..nplanes {
+        let (w, h) = buf.get_dimensions(plane);
+        let off    = buf.get_offset(plane);
+        let stride = buf.get_stride(plane);
+        vp_deblock_plane(buf.get_data_mut().unwrap(), off, stride, w, h, loop_str);
+    }
+}
+
+// BT.601 limited range conversion, only the colour planes of the source are used
+pub fn vp_yuv2rgb(src: &NAVideoBuffer<u8>, dst: &mut NAVideoBuffer<u8>) {
+    const Y_COEF: i32  = 76309;
+    const RV_COEF: i32 = 104597;
+    const GU_COEF: i32 = 25675;
+    const GV_COEF: i32 = 53279;
+    const BU_COEF: i32 = 132201;
+
+    let (width, height) = src.get_dimensions(0);
+    let ystride = src.get_stride(0);
+    let ustride = src.get_stride(1);
+    let vstride = src.get_stride(2);
+    let data = src.get_data();
+    let ydata = &data[src.get_offset(0)..];
+    let udata = &data[src.get_offset(1)..];
+    let vdata = &data[src.get_offset(2)..];
+    let dstride = dst.get_stride(0);
+    let doff = dst.get_offset(0);
+    let ddata = dst.get_data_mut().unwrap();
+
+    let clip = |val: i32| ((val + (1 << 15)) >> 16).clamp(0, 255) as u8;
+    for (y, dline) in ddata[doff..].chunks_mut(dstride).take(height).enumerate() {
+        let yline = &ydata[y * ystride..][..width];
+        let uline = &udata[(y >> 1) * ustride..];
+        let vline = &vdata[(y >> 1) * vstride..];
+        for (x, (pix, &luma)) in dline.chunks_exact_mut(3).zip(yline.iter()).enumerate() {
+            let l = (i32::from(luma) - 16) * Y_COEF;
+            let u = i32::from(uline[x >> 1]) - 128;
+            let v = i32::from(vline[x >> 1]) - 128;
+            pix[0] = clip(l + RV_COEF * v);
+            pix[1] = clip(l - GU_COEF * u - GV_COEF * v);
+            pix[2] = clip(l + BU_COEF * u);
+        }
+    }
+}
+
+// floating-point version of vp_yuv2rgb() for the tests
+#[cfg(test)]
+pub(crate) fn ref_yuv2rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let l = 1.164 * (f32::from(y) - 16.0);
+    let u = f32::from(u) - 128.0;
+    let v = f32::from(v) - 128.0;
+    let clip = |val: f32| val.round().clamp(0.0, 255.0) as u8;
+    [clip(l + 1.596 * v), clip(l - 0.392 * u - 0.813 * v), clip(l + 2.017 * u)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gen_picture(width: usize, height: usize, pixfunc: impl Fn(usize, usize) -> u8) -> NAVideoBufferRef<u8> {
+        let vinfo = NAVideoInfo::new(width, height, false, YUV420_FORMAT);
+        let mut buf = alloc_video_buffer(vinfo, 4).unwrap().get_vbuf().unwrap();
+        let stride = buf.get_stride(0);
+        let offset = buf.get_offset(0);
+        let data = buf.get_data_mut().unwrap();
+        for (y, line) in data[offset..].chunks_mut(stride).take(height).enumerate() {
+            for (x, el) in line.iter_mut().take(width).enumerate() {
+                *el = pixfunc(x, y);
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let pic1 = gen_picture(176, 144, |x, y| ((x + y) & 0xFF) as u8);
+        let pic2 = gen_picture(176, 144, |x, y| ((x + y) & 0xFF) as u8);
+        let noisy = gen_picture(176, 144, |x, y| ((x + y + (x * 7 + y * 13) % 3) & 0xFF) as u8);
+        let other = gen_picture(176, 144, |x, y| if ((x / 22) + (y / 18)) & 1 == 0 { 16 } else { 235 });
+
+        let fp1 = vp_fingerprint(&pic1);
+        let fp2 = vp_fingerprint(&pic2);
+        let fp3 = vp_fingerprint(&noisy);
+        let fp4 = vp_fingerprint(&other);
+        assert_eq!(fp1, fp2);
+        assert!((fp1 ^ fp3).count_ones() <= 4);
+        assert!((fp1 ^ fp4).count_ones() >= 16);
+
+        // pictures differing only outside the visible area
+        let mut padded1 = gen_picture(176, 144, |x, y| if x < 160 && y < 128 { ((x + y) & 0xFF) as u8 } else { 0 });
+        let mut padded2 = gen_picture(176, 144, |x, y| if x < 160 && y < 128 { ((x + y) & 0xFF) as u8 } else { 255 });
+        assert_ne!(vp_fingerprint(&padded1), vp_fingerprint(&padded2));
+        padded1.set_crop_rect(Some((0, 0, 160, 128)));
+        padded2.set_crop_rect(Some((0, 0, 160, 128)));
+        assert_eq!(vp_fingerprint(&padded1), vp_fingerprint(&padded2));
+        let visible = gen_picture(160, 128, |x, y| ((x + y) & 0xFF) as u8);
+        assert_eq!(vp_fingerprint(&padded1), vp_fingerprint(&visible));
+    }
+
+    #[test]
+    fn test_deblock() {
+        fn edge_diff(buf: &NAVideoBufferRef<u8>) -> u32 {
+            let stride = buf.get_stride(0);
+            let data = &buf.get_data()[buf.get_offset(0)..];
+            let mut sum = 0;
+            for y in 0..64 {
+                for x in (8..64).step_by(8) {
+                    sum += (i32::from(data[x + y * stride]) - i32::from(data[x - 1 + y * stride])).unsigned_abs();
+                }
+            }
+            for y in (8..64).step_by(8) {
+                for x in 0..64 {
+                    sum += (i32::from(data[x + y * stride]) - i32::from(data[x + (y - 1) * stride])).unsigned_abs();
+                }
+            }
+            sum
+        }
+
+        let mut pic = gen_picture(64, 64, |x, y| if ((x / 8) + (y / 8)) & 1 == 0 { 100 } else { 106 });
+        let flat = gen_picture(64, 64, |_, _| 100);
+        let orig_diff = edge_diff(&pic);
+        let stride = pic.get_stride(0);
+        let off = pic.get_offset(0);
+        vp_deblock_plane(pic.get_data_mut().unwrap(), off, stride, 64, 64, 0);
+        assert_eq!(edge_diff(&pic), orig_diff);
+        vp_deblock_plane(pic.get_data_mut().unwrap(), off, stride, 64, 64, 8);
+        assert!(edge_diff(&pic) * 2 < orig_diff);
+
+        let mut flat2 = gen_picture(64, 64, |_, _| 100);
+        vp_deblock_plane(flat2.get_data_mut().unwrap(), off, stride, 64, 64, 8);
+        assert_eq!(flat.get_data(), flat2.get_data());
+    }
+
+    #[test]
+    fn test_postprocess() {
+        let blocky = |x: usize, y: usize| if ((x / 8) + (y / 8)) & 1 == 0 { 100 } else { 140 };
+        let orig = gen_picture(64, 64, blocky);
+        let mut pics = Vec::new();
+        for level in 0..=VP_MAX_POSTPROC_LEVEL {
+            let mut pic = gen_picture(64, 64, blocky);
+            for plane in 1..3 {
+                let off = pic.get_offset(plane);
+                let stride = pic.get_stride(plane);
+                for (y, line) in pic.get_data_mut().unwrap()[off..].chunks_mut(stride).take(32).enumerate() {
+                    for (x, el) in line.iter_mut().take(32).enumerate() {
+                        *el = blocky(x, y);
+                    }
+                }
+            }
+            vp_postprocess(&mut pic, level, 6);
+            pics.push(pic);
+        }
+        let plane = |pic: &NAVideoBufferRef<u8>, plane: usize| {
+                let off = pic.get_offset(plane);
+                pic.get_data()[off..][..pic.get_stride(plane) * pic.get_dimensions(plane).1].to_vec()
+            };
+        assert_eq!(plane(&pics[0], 0), plane(&orig, 0));
+        assert_ne!(plane(&pics[1], 0), plane(&pics[0], 0));
+        assert_eq!(plane(&pics[1], 1), plane(&pics[0], 1));
+        assert_eq!(plane(&pics[2], 0), plane(&pics[1], 0));
+        assert_ne!(plane(&pics[2], 1), plane(&pics[1], 1));
+        assert_ne!(plane(&pics[3], 0), plane(&pics[2], 0));
+    }
+
+    #[test]
+    fn test_yuv2rgb() {
+        let mut pic = gen_picture(32, 16, |x, y| (x * 7 + y * 3) as u8);
+        for plane in 1..3 {
+            let off = pic.get_offset(plane);
+            let stride = pic.get_stride(plane);
+            for (y, line) in pic.get_data_mut().unwrap()[off..].chunks_mut(stride).take(8).enumerate() {
+                for (x, el) in line.iter_mut().take(16).enumerate() {
+                    *el = if plane == 1 { (x * 16 + y) as u8 } else { (255 - x * 13 - y * 4) as u8 };
+                }
+            }
+        }
+        let mut rgb = alloc_video_buffer(NAVideoInfo::new(32, 16, false, RGB24_FORMAT), 0).unwrap().get_vbuf().unwrap();
+        vp_yuv2rgb(&pic, &mut rgb);
+
+        let stride = rgb.get_stride(0);
+        for y in 0..16 {
+            for x in 0..32 {
+                let luma = pic.get_data()[pic.get_offset(0) + x + y * pic.get_stride(0)];
+                let u = pic.get_data()[pic.get_offset(1) + x / 2 + y / 2 * pic.get_stride(1)];
+                let v = pic.get_data()[pic.get_offset(2) + x / 2 + y / 2 * pic.get_stride(2)];
+                let ref_pix = ref_yuv2rgb(luma, u, v);
+                let pix = &rgb.get_data()[x * 3 + y * stride..][..3];
+                for (&a, &b) in pix.iter().zip(ref_pix.iter()) {
+                    assert!((i16::from(a) - i16::from(b)).abs() <= 1);
+                }
+            }
+        }
+    }
+}